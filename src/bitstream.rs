@@ -1,6 +1,6 @@
-use std::{collections::HashMap, fmt::Write};
+use std::{collections::{HashMap, HashSet}, fmt::Write};
 
-use crate::{chipdb::{ChipDb, ConfiguredArc, TilePos}, pnr::{IoPinSpot, PnrProblem, PnrSolution}};
+use crate::{chipdb::{ChipDb, ConfiguredArc, ExtraCell, TilePos}, pnr::{ClockEdge, InputSpot, IoPinSpot, LutIndex, MAC16_PARAMETERS, OutputSpot, PllDividers, PllReference, PnrProblem, PnrSolution, Ram, ResetMode, ResetValue, RgbCurrentMode, UsedIo}};
 
 pub struct BitMatrix {
   pub rows: usize,
@@ -23,6 +23,21 @@ impl BitMatrix {
     }
     Ok(())
   }
+
+  /// Writes each row as hex digits, most significant first, where column 0 is the least significant bit.
+  /// This is how .ram_data blocks are written.
+  pub fn serialize_hex(&self, w: &mut impl Write) -> std::fmt::Result {
+    self.assert_valid();
+    assert_eq!(self.cols % 4, 0);
+    for row in 0..self.rows {
+      for digit in (0..self.cols / 4).rev() {
+        let value = (0..4).filter(|bit| self.data[row * self.cols + 4 * digit + bit]).map(|bit| 1 << bit).sum::<u32>();
+        w.write_char(char::from_digit(value, 16).unwrap())?;
+      }
+      w.write_char('\n')?;
+    }
+    Ok(())
+  }
 }
 
 pub struct BitStreamEntry {
@@ -34,6 +49,9 @@ pub struct BitStreamEntry {
 pub struct BitStream {
   pub entries: Vec<BitStreamEntry>,
   pub tile_to_entry_index: HashMap<TilePos, usize>,
+  /// The bits the design has written so far, and to what, so that two parts of it asking for the same bit are still
+  /// caught when the base bitstream already has some bits set.
+  written_bits: HashMap<(TilePos, usize, usize), bool>,
 }
 
 impl BitStream {
@@ -60,11 +78,15 @@ impl BitStream {
         assert_eq!(tile_to_entry_index.insert(xy, i), None);
       }
     }
-    BitStream { entries, tile_to_entry_index }
+    BitStream { entries, tile_to_entry_index, written_bits: HashMap::new() }
   }
 
   pub fn set_bit_row_col(&mut self, xy: TilePos, row: usize, col: usize) {
-    println!(" -- Setting bit for tile {:?} at [{}, {}]", xy, row, col);
+    self.write_bit_row_col(xy, row, col, true);
+  }
+
+  fn write_bit_row_col(&mut self, xy: TilePos, row: usize, col: usize, value: bool) {
+    println!(" -- {} bit for tile {:?} at [{}, {}]", if value { "Setting" } else { "Clearing" }, xy, row, col);
     let entry_index = match self.tile_to_entry_index.get(&xy) {
       Some(&i) => i,
       None => panic!("No entry for tile {:?}", xy),
@@ -72,18 +94,57 @@ impl BitStream {
     let entry = &mut self.entries[entry_index];
     assert!(row < entry.matrix.rows);
     assert!(col < entry.matrix.cols);
-    let bit_index = row * entry.matrix.cols + col;
-    assert!(!entry.matrix.data[bit_index]);
-    entry.matrix.data[bit_index] = true;
+    // Only a bit that has to be clear may be asked for twice.
+    if let Some(old_value) = self.written_bits.insert((xy, row, col), value) {
+      assert!(!old_value && !value, "Bit [{}, {}] of tile {:?} is already set", row, col, xy);
+    }
+    entry.matrix.data[row * entry.matrix.cols + col] = value;
   }
 
   pub fn set_bit(&mut self, xy: TilePos, bit_desc: &str) {
+    self.write_bit(xy, bit_desc, true);
+  }
+
+  fn write_bit(&mut self, xy: TilePos, bit_desc: &str, value: bool) {
     // The desc is always like "B<row>[<col>]".
     assert!(bit_desc.starts_with('B'));
     let mut parts = bit_desc[1..].split('[');
     let row: usize = parts.next().unwrap().parse().unwrap();
     let col: usize = parts.next().unwrap().trim_end_matches(']').parse().unwrap();
-    self.set_bit_row_col(xy, row, col);
+    self.write_bit_row_col(xy, row, col, value);
+  }
+
+  /// The device the bitstream is for, from its .device line.
+  pub fn device(&self) -> Option<&str> {
+    self.entries.iter().find(|entry| entry.name == "device").and_then(|entry| entry.args.first()).map(|arg| arg.as_str())
+  }
+
+  /// Sets a configuration bit by its name in the chipdb, like "CarryInSet" in a "logic" tile.
+  pub fn set_named_bit(&mut self, chipdb: &ChipDb, tile_type: &str, xy: TilePos, name: &str) -> Result<(), String> {
+    // Bits starting with '!' are the ones that must be clear, which a non-empty base bitstream might have set.
+    for bit in chipdb.get_tile_bits(tile_type, name)? {
+      match bit.strip_prefix('!') {
+        Some(bit) => self.write_bit(xy, bit, false),
+        None => self.write_bit(xy, bit, true),
+      }
+    }
+    Ok(())
+  }
+
+  /// Sets the initial contents of the block RAM whose bottom tile is `xy`, as 16 rows of 256 bits.
+  pub fn set_ram_data(&mut self, xy: TilePos, words: &[u16]) {
+    let mut data = vec![false; 16 * 256];
+    for (i, word) in words.iter().enumerate() {
+      for bit in 0..16 {
+        data[16 * i + bit] = (word >> bit) & 1 != 0;
+      }
+    }
+    let args = vec![xy.0.to_string(), xy.1.to_string()];
+    let entry = BitStreamEntry { name: "ram_data".to_string(), args, matrix: BitMatrix { rows: 16, cols: 256, data } };
+    match self.entries.iter_mut().find(|e| e.name == entry.name && e.args == entry.args) {
+      Some(existing) => *existing = entry,
+      None => self.entries.push(entry),
+    }
   }
 }
 
@@ -102,6 +163,8 @@ pub fn parse(content: &str) -> Result<BitStream, String> {
     let mut parts = line.split_whitespace();
     let name = parts.next().unwrap();
     let args = parts.map(|s| s.to_string()).collect();
+    // RAM contents are written in hex, and every other block in binary.
+    let is_hex = name == "ram_data";
     let mut rows = 0;
     let mut cols = None;
     let mut data = Vec::new();
@@ -109,19 +172,29 @@ pub fn parse(content: &str) -> Result<BitStream, String> {
       if line.is_empty() || line.starts_with('.') {
         break;
       }
+      let line_cols = if is_hex { 4 * line.len() } else { line.len() };
       match cols {
-        None => cols = Some(line.len()),
-        Some(expected_cols) if line.len() != expected_cols => {
-          return Err(format!("Expected {} columns, got {}", expected_cols, line.len()));
+        None => cols = Some(line_cols),
+        Some(expected_cols) if line_cols != expected_cols => {
+          return Err(format!("Expected {} columns, got {}", expected_cols, line_cols));
         }
         _ => {}
       }
       let line = lines.next().unwrap();
-      for c in line.chars() {
-        if c != '0' && c != '1' {
-          return Err(format!("Invalid character in data block: '{}'", c));
+      if is_hex {
+        let mut row = Vec::new();
+        for c in line.chars().rev() {
+          let value = c.to_digit(16).ok_or_else(|| format!("Invalid character in data block: '{}'", c))?;
+          row.extend((0..4).map(|bit| (value >> bit) & 1 != 0));
+        }
+        data.extend(row);
+      } else {
+        for c in line.chars() {
+          if c != '0' && c != '1' {
+            return Err(format!("Invalid character in data block: '{}'", c));
+          }
+          data.push(c == '1');
         }
-        data.push(c == '1');
       }
       rows += 1;
     }
@@ -135,6 +208,32 @@ pub fn parse(content: &str) -> Result<BitStream, String> {
   Ok(BitStream::from_entries(entries))
 }
 
+/// The bitstream of a design that uses nothing, for devices without a built-in one. Every tile of the chipdb has
+/// all of its bits clear, except that the column buffers are all turned on, so that the global networks reach every tile.
+pub fn empty(chipdb: &ChipDb) -> Result<BitStream, String> {
+  let mut tiles: Vec<_> = chipdb.tile_types.iter().collect();
+  tiles.sort_by_key(|(tile, _)| (tile.1, tile.0));
+  let mut entries = vec![
+    BitStreamEntry { name: "comment".to_string(), args: vec!["from".to_string(), "ice40pnr".to_string()], matrix: BitMatrix { rows: 0, cols: 0, data: vec![] } },
+    BitStreamEntry { name: "device".to_string(), args: vec![chipdb.device.clone()], matrix: BitMatrix { rows: 0, cols: 0, data: vec![] } },
+  ];
+  for (tile, tile_type) in tiles {
+    let &(cols, rows) = chipdb.tile_bit_sizes.get(tile_type).ok_or_else(|| format!("No size for {} tiles in the chipdb", tile_type))?;
+    let args = vec![tile.0.to_string(), tile.1.to_string()];
+    entries.push(BitStreamEntry { name: format!("{}_tile", tile_type), args, matrix: BitMatrix { rows, cols, data: vec![false; rows * cols] } });
+  }
+  let mut bs = BitStream::from_entries(entries);
+  for &tile in &chipdb.column_buffer_tiles {
+    let tile_type = chipdb.get_tile_type(tile)?;
+    for global_net_index in 0..8 {
+      bs.set_named_bit(chipdb, tile_type, tile, &format!("ColBufCtrl.glb_netwk_{}", global_net_index))?;
+    }
+  }
+  // These are part of the base bitstream, not of the design.
+  bs.written_bits.clear();
+  Ok(bs)
+}
+
 pub fn serialize(bitstream: &BitStream, w: &mut impl Write) -> std::fmt::Result {
   let only_one_newline = [
     "comment",
@@ -147,7 +246,10 @@ pub fn serialize(bitstream: &BitStream, w: &mut impl Write) -> std::fmt::Result
       write!(w, " {}", arg)?;
     }
     w.write_char('\n')?;
-    entry.matrix.serialize(w)?;
+    match entry.name.as_str() {
+      "ram_data" => entry.matrix.serialize_hex(w)?,
+      _ => entry.matrix.serialize(w)?,
+    }
     if !only_one_newline.contains(&&entry.name[..]) {
       w.write_char('\n')?;
     }
@@ -165,40 +267,69 @@ pub fn add_arcs_and_luts(
   assert_eq!(problem.lut4s.len(), solution.lut_placements.len());
   let mut clock_domains = HashMap::new();
   let mut extra_arcs = Vec::new();
-  for (lut, &(tile, lut_number)) in problem.lut4s.iter().zip(&solution.lut_placements) {
+  let set_reset_luts: HashSet<LutIndex> = problem.wires.iter().filter_map(|wire| match wire.to {
+    InputSpot::SetReset { lut_index } => Some(lut_index),
+    _ => None,
+  }).collect();
+  let carry_luts: HashSet<LutIndex> = problem.carry_chains.iter().flat_map(|chain| chain.luts.iter().copied()).collect();
+  for (lut_index, (lut, &(tile, lut_number))) in problem.lut4s.iter().zip(&solution.lut_placements).enumerate() {
     println!("Configuring LUT {:?} at {:?} with table {:016b}", lut, tile, lut.table);
-    // Set all bits for the actual lookup table.
-    let table_bit_row_offset_and_column = [
-      (0, 40), (1, 40), (1, 41), (0, 41), (0, 42), (1, 42), (1, 43), (0, 43),
-      (0, 39), (1, 39), (1, 38), (0, 38), (0, 37), (1, 37), (1, 36), (0, 36),
-    ];
-    // let carry_enable_row_offset_and_column = (0, 44);
-    let dff_enable_row_offset_and_column = (0, 45);
-    // let set_no_reset_row_offset_and_column = (1, 44);
-    // let async_set_row_offset_and_column = (1, 45);
-    for i in 0..16 {
+    // The chipdb lists the 20 bits of each logic cell as LC_<n>. The lookup table is shuffled around bits 0 to 7
+    // and 10 to 17, and the rest are the carry enable (8), DFF enable (9), set not reset (18) and async set/reset (19).
+    let lc_bits = chipdb.get_tile_bits("logic", &format!("LC_{}", lut_number)).unwrap();
+    let table_bit_indices = [4, 14, 15, 5, 6, 16, 17, 7, 3, 13, 12, 2, 1, 11, 10, 0];
+    let carry_enable_index = 8;
+    let dff_enable_index = 9;
+    let set_no_reset_index = 18;
+    let async_set_index = 19;
+    for (i, &bit_index) in table_bit_indices.iter().enumerate() {
       if (lut.table >> i) & 1 != 0 {
-        let (row_offset, column) = table_bit_row_offset_and_column[i];
-        let row = 2 * lut_number as usize + row_offset;
-        println!(" Setting bit for LUT {:016b}: {:?}[{}][{}]", lut.table, tile, row, column);
-        bs.set_bit_row_col(tile, row, column);
+        println!(" Setting bit for LUT {:016b}: {:?}{}", lut.table, tile, lc_bits[bit_index]);
+        bs.set_bit(tile, &lc_bits[bit_index]);
       }
     }
+    if carry_luts.contains(&LutIndex(lut_index)) {
+      println!(" Setting bit for LUT carry enable: {:?}{}", tile, lc_bits[carry_enable_index]);
+      bs.set_bit(tile, &lc_bits[carry_enable_index]);
+    }
     if let Some(clock_domain) = lut.clock_domain {
-      let (row_offset, column) = dff_enable_row_offset_and_column;
-      let row = 2 * lut_number as usize + row_offset;
-      println!(" Setting bit for LUT DFF enable: {:?}[{}][{}]", tile, row, column);
-      bs.set_bit_row_col(tile, row, column);
+      println!(" Setting bit for LUT DFF enable: {:?}{}", tile, lc_bits[dff_enable_index]);
+      bs.set_bit(tile, &lc_bits[dff_enable_index]);
       match clock_domains.insert(tile, clock_domain) {
         None => {
           // For now I only support the global clock networks.
           assert!(clock_domain < 8);
-          let from = chipdb.get_net_by_name(TilePos(1, 1), &format!("glb_netwk_{}", clock_domain)).unwrap();
+          let from = chipdb.get_net_by_name(tile, &format!("glb_netwk_{}", clock_domain)).unwrap();
           let to = chipdb.get_net_by_name(tile, "lutff_global/clk").unwrap();
           extra_arcs.push(chipdb.get_configured_arc_between(from, to).unwrap());
+          // The placer keeps flip-flops with different clock edges in different tiles, so this is shared.
+          if lut.clock_edge == ClockEdge::Falling {
+            println!(" Setting NegClk for tile {:?}", tile);
+            bs.set_named_bit(chipdb, "logic", tile, "NegClk").unwrap();
+          }
         }
+        // The placer never puts flip-flops with different clock domains in one tile.
         Some(old) => assert_eq!(old, clock_domain),
       }
+      if set_reset_luts.contains(&LutIndex(lut_index)) {
+        if lut.reset_value == ResetValue::Set {
+          println!(" Setting bit for LUT DFF set (not reset): {:?}{}", tile, lc_bits[set_no_reset_index]);
+          bs.set_bit(tile, &lc_bits[set_no_reset_index]);
+        }
+        if lut.reset_mode == ResetMode::Async {
+          println!(" Setting bit for LUT DFF async set/reset: {:?}{}", tile, lc_bits[async_set_index]);
+          bs.set_bit(tile, &lc_bits[async_set_index]);
+        }
+      }
+    }
+  }
+
+  // A carry chain that starts with a carry in of 1 gets it from the CarryInSet bit of its first tile.
+  for chain in &problem.carry_chains {
+    if chain.carry_in {
+      let (tile, _) = solution.lut_placements[chain.luts[0].0];
+      println!(" Setting CarryInSet for tile {:?}", tile);
+      bs.set_named_bit(chipdb, "logic", tile, "CarryInSet").unwrap();
     }
   }
 
@@ -227,28 +358,554 @@ pub fn add_configured_arc(
   }
 }
 
+pub fn add_rams(
+  bs: &mut BitStream,
+  chipdb: &ChipDb,
+  problem: &PnrProblem,
+  solution: &PnrSolution,
+) -> Result<(), String> {
+  for (ram, &tile) in problem.rams.iter().zip(&solution.ram_placements) {
+    println!("Configuring RAM {:?} at {:?}", ram.name, tile);
+    let Ram { read_mode, write_mode, read_clock_edge, write_clock_edge, init, .. } = ram;
+    let top = TilePos(tile.0, tile.1 + 1);
+    // Only some chips need their RAMs powered up.
+    if chipdb.get_tile_bits("ramb", "RamConfig.PowerUp").is_ok() {
+      bs.set_named_bit(chipdb, "ramb", tile, "RamConfig.PowerUp")?;
+    }
+    if *write_clock_edge == ClockEdge::Falling {
+      bs.set_named_bit(chipdb, "ramb", tile, "NegClk")?;
+    }
+    if *read_clock_edge == ClockEdge::Falling {
+      bs.set_named_bit(chipdb, "ramt", top, "NegClk")?;
+    }
+    let cbits = write_mode.mode_bits() | read_mode.mode_bits() << 2;
+    for k in 0..4 {
+      if (cbits >> k) & 1 != 0 {
+        bs.set_named_bit(chipdb, "ramt", top, &format!("RamConfig.CBIT_{}", k))?;
+      }
+    }
+    bs.set_ram_data(tile, init);
+  }
+  Ok(())
+}
+
+/// Sets the configuration bit an extra cell calls `name`, which is some "CBIT_<n>" or "PLLCONFIG_<n>" in one of its tiles,
+/// or for the other hard IP blocks, any bit of the tile, like "cbit2usealt_in_0".
+pub fn set_extra_cell_bit(bs: &mut BitStream, chipdb: &ChipDb, extra_cell: &ExtraCell, name: &str) -> Result<(), String> {
+  let no_bit = || format!("{} has no configuration bit {}", extra_cell.describe(), name);
+  let Some((tile, cbit)) = extra_cell.entries.get(name) else {
+    return Err(no_bit());
+  };
+  let tile_type = chipdb.get_tile_type(*tile)?;
+  if ExtraCell::is_config_bit(cbit) {
+    let prefix = if cbit.starts_with("PLLCONFIG_") { "PLL" } else { "IpConfig" };
+    return bs.set_named_bit(chipdb, tile_type, *tile, &format!("{}.{}", prefix, cbit));
+  }
+  let bit = chipdb.find_tile_bit(tile_type, cbit).ok_or_else(no_bit)?;
+  bs.set_named_bit(chipdb, tile_type, *tile, bit)
+}
+
+/// Sets the bits of a parameter of an extra cell. Parameters more than one bit wide have a configuration bit
+/// for each bit, like TOPOUTPUT_SELECT_1.
+fn set_extra_cell_parameter(
+  bs: &mut BitStream,
+  chipdb: &ChipDb,
+  extra_cell: &ExtraCell,
+  parameter: &str,
+  width: usize,
+  value: u64,
+) -> Result<(), String> {
+  for i in (0..width).filter(|i| (value >> i) & 1 != 0) {
+    match width {
+      1 => set_extra_cell_bit(bs, chipdb, extra_cell, parameter)?,
+      _ => set_extra_cell_bit(bs, chipdb, extra_cell, &format!("{}_{}", parameter, i))?,
+    }
+  }
+  Ok(())
+}
+
+pub fn add_dsps(
+  bs: &mut BitStream,
+  chipdb: &ChipDb,
+  problem: &PnrProblem,
+  solution: &PnrSolution,
+) -> Result<(), String> {
+  for (dsp, &site) in problem.dsps.iter().zip(&solution.dsp_placements) {
+    let extra_cell = &chipdb.extra_cells[site];
+    println!("Configuring DSP {:?} at {}", dsp.name, extra_cell.describe());
+    for (parameter, width) in MAC16_PARAMETERS {
+      let value = dsp.parameters.get(parameter).copied().unwrap_or(0);
+      set_extra_cell_parameter(bs, chipdb, extra_cell, parameter, width, value)?;
+    }
+  }
+  Ok(())
+}
+
+pub fn add_sprams(
+  bs: &mut BitStream,
+  chipdb: &ChipDb,
+  problem: &PnrProblem,
+  solution: &PnrSolution,
+) -> Result<(), String> {
+  for (spram, &site) in problem.sprams.iter().zip(&solution.spram_placements) {
+    let extra_cell = &chipdb.extra_cells[site];
+    println!("Configuring SPRAM {:?} at {}", spram.name, extra_cell.describe());
+    set_extra_cell_bit(bs, chipdb, extra_cell, "SPRAM_EN")?;
+  }
+  Ok(())
+}
+
+/// Sets the clock divider of the 48 MHz oscillator, if it's used.
+pub fn add_oscillators(bs: &mut BitStream, chipdb: &ChipDb, problem: &PnrProblem) -> Result<(), String> {
+  let Some(divider) = problem.wires.iter().find_map(|wire| match wire.from {
+    OutputSpot::HfOsc { divider } => Some(divider),
+    _ => None,
+  }) else {
+    return Ok(());
+  };
+  let hfosc = chipdb.find_extra_cell("HFOSC")?;
+  println!("Configuring {} with divider {}", hfosc.describe(), divider);
+  set_extra_cell_parameter(bs, chipdb, hfosc, "CLKHF_DIV", 2, divider as u64)
+}
+
+pub fn add_rgb_driver(bs: &mut BitStream, chipdb: &ChipDb, problem: &PnrProblem) -> Result<(), String> {
+  let Some(rgb_driver) = &problem.rgb_driver else {
+    return Ok(());
+  };
+  let extra_cell = chipdb.find_extra_cell("RGBA_DRV")?;
+  println!("Configuring the RGB driver {:?} at {}", rgb_driver.name, extra_cell.describe());
+  set_extra_cell_bit(bs, chipdb, extra_cell, "RGBA_DRV_EN")?;
+  if rgb_driver.current_mode == RgbCurrentMode::Half {
+    set_extra_cell_bit(bs, chipdb, extra_cell, "CURRENT_MODE")?;
+  }
+  for (parameter, current) in [
+    ("RGB0_CURRENT", rgb_driver.rgb0_current),
+    ("RGB1_CURRENT", rgb_driver.rgb1_current),
+    ("RGB2_CURRENT", rgb_driver.rgb2_current),
+  ] {
+    set_extra_cell_parameter(bs, chipdb, extra_cell, parameter, 6, current as u64)?;
+  }
+  Ok(())
+}
+
+pub fn add_plls(
+  bs: &mut BitStream,
+  chipdb: &ChipDb,
+  problem: &PnrProblem,
+  solution: &PnrSolution,
+) -> Result<(), String> {
+  for (pll, &site) in problem.plls.iter().zip(&solution.pll_placements) {
+    let extra_cell = &chipdb.extra_cells[site];
+    let PllDividers { divr, divf, divq, filter_range } = pll.dividers;
+    println!("Configuring PLL {:?} at {}", pll.name, extra_cell.describe());
+    let pll_type = match pll.reference {
+      PllReference::Pad => 2,
+      PllReference::Core => 3,
+    };
+    // Everything else, like the fine delay adjustment and the output select, is left at 0.
+    for (parameter, width, value) in [
+      ("PLLTYPE", 3, pll_type),
+      // Simple feedback.
+      ("FEEDBACK_PATH", 3, 1),
+      ("DIVR", 4, divr),
+      ("DIVF", 7, divf),
+      ("DIVQ", 3, divq),
+      ("FILTER_RANGE", 3, filter_range),
+    ] {
+      set_extra_cell_parameter(bs, chipdb, extra_cell, parameter, width, value as u64)?;
+    }
+  }
+  Ok(())
+}
+
+/// Sets the enable bits of each hard IP block, and whatever other bits it asks for.
+pub fn add_hard_ips(
+  bs: &mut BitStream,
+  chipdb: &ChipDb,
+  problem: &PnrProblem,
+  solution: &PnrSolution,
+) -> Result<(), String> {
+  for (hard_ip, &site) in problem.hard_ips.iter().zip(&solution.hard_ip_placements) {
+    let extra_cell = &chipdb.extra_cells[site];
+    println!("Configuring {} {:?} at {}", hard_ip.cell_type, hard_ip.name, extra_cell.describe());
+    let mut enable_bits: Vec<_> = extra_cell.entries.keys().filter(|key| ExtraCell::is_enable_bit(key)).collect();
+    enable_bits.sort();
+    for name in enable_bits.into_iter().chain(&hard_ip.config) {
+      set_extra_cell_bit(bs, chipdb, extra_cell, name)?;
+    }
+  }
+  Ok(())
+}
+
 pub fn set_io_pin(
   bs: &mut BitStream,
-  io_pin_spot: IoPinSpot,
-  is_output: bool,
+  chipdb: &ChipDb,
+  io: &UsedIo,
 ) -> Result<(), String> {
-  println!("Setting IO pin {:?} as {}.", io_pin_spot, if is_output { "output" } else { "input" });
-  let IoPinSpot { tile, which } = io_pin_spot;
+  let UsedIo { spot, direction, config, .. } = io;
+  let pin_type = config.pin_type(direction.has_output());
+  println!("Setting IO pin {:?} as {:?} with PIN_TYPE {:06b}.", spot, direction, pin_type);
+  let IoPinSpot { tile, which } = *spot;
   assert!(which == 0 || which == 1);
-  // Set the bizarre "IoCtrl cf_bit_39" or "IoCtrl cf_bit_35" thing.
-  bs.set_bit(tile, if which == 0 { "B6[15]" } else { "B12[15]" });
-  // Set IOB_{which} PINTYPE_0.
-  bs.set_bit(tile, if which == 0 { "B3[17]" } else { "B13[17]" });
-  // Set REN_{which}.
-  bs.set_bit(tile, if which == 0 { "B1[3]" } else { "B6[2]" });
-  if is_output {
-    // Set IOB_{which} PINTYPE_3.
-    bs.set_bit(tile, if which == 0 { "B0[16]" } else { "B10[16]" });
-    // Set IOB_{which} PINTYPE_4.
-    bs.set_bit(tile, if which == 0 { "B4[16]" } else { "B14[16]" });
-  } else {
-    // Set IE_{which}.
-    bs.set_bit(tile, if which == 0 { "B6[3]" } else { "B9[3]" });
+  let mut set_tile_bits = |tile: TilePos, name: &str| bs.set_named_bit(chipdb, "io", tile, name);
+  for k in 0..6 {
+    if (pin_type >> k) & 1 != 0 {
+      set_tile_bits(tile, &format!("IOB_{}.PINTYPE_{}", which, k))?;
+    }
+  }
+  // The input enable and pull-up of a pin are often configured from some other IO tile, given by the .ieren section.
+  let IoPinSpot { tile: ieren_tile, which: ieren_which } = chipdb.ieren.get(spot).copied().unwrap_or(*spot);
+  // On the 1k parts both bits are active low, so IE is set to disable the input and REN to enable the pull-up.
+  let active_low = chipdb.device == "1k";
+  if direction.has_input() != active_low {
+    set_tile_bits(ieren_tile, &format!("IoCtrl.IE_{}", ieren_which))?;
+  }
+  if config.pull_up == active_low {
+    // Setting REN disables the pull-up resistor (or on the 1k parts, enables it).
+    set_tile_bits(ieren_tile, &format!("IoCtrl.REN_{}", ieren_which))?;
+  }
+  if !config.pull_up {
+    // On the 5k the pull-up also has to be turned off with the bizarre "IoCtrl cf_bit_39" or "IoCtrl cf_bit_35" thing.
+    let cf_bit = if which == 0 { "IoCtrl.cf_bit_39" } else { "IoCtrl.cf_bit_35" };
+    if chipdb.get_tile_bits("io", cf_bit).is_ok() {
+      set_tile_bits(tile, cf_bit)?;
+    }
   }
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pnr::{IoConfig, IoDirection, OutputEnable};
+
+  fn set_bits(bs: &BitStream, xy: TilePos) -> Vec<(usize, usize)> {
+    let matrix = &bs.entries[bs.tile_to_entry_index[&xy]].matrix;
+    (0..matrix.rows * matrix.cols).filter(|&i| matrix.data[i]).map(|i| (i / matrix.cols, i % matrix.cols)).collect()
+  }
+
+  #[test]
+  fn io_pins_take_their_input_enable_from_the_ieren_tile() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".io_tile_bits 18 16\n",
+      "IOB_1.PINTYPE_0 B13[17]\n",
+      "IOB_1.PINTYPE_3 B10[16]\n",
+      "IOB_1.PINTYPE_4 B14[16]\n",
+      "IoCtrl.IE_0 B6[3]\n",
+      "IoCtrl.REN_0 B1[3]\n",
+      "IoCtrl.cf_bit_35 B12[15]\n",
+      ".ieren\n",
+      "5 0 1 6 0 0\n",
+    )).unwrap();
+    let empty_tile = |x: u8| format!(".io_tile {} 0\n{}", x, "000000000000000000\n".repeat(16));
+    let mut bs = parse(&(empty_tile(5) + &empty_tile(6))).unwrap();
+    let spot = IoPinSpot { tile: TilePos(5, 0), which: 1 };
+    let io = UsedIo { name: None, spot, direction: IoDirection::Input, config: IoConfig::default() };
+    set_io_pin(&mut bs, &chipdb, &io).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(5, 0)), [(12, 15), (13, 17)]);
+    assert_eq!(set_bits(&bs, TilePos(6, 0)), [(1, 3), (6, 3)]);
+
+    let mut bs = parse(&(empty_tile(5) + &empty_tile(6))).unwrap();
+    let config = IoConfig { pull_up: true, ..IoConfig::default() };
+    let io = UsedIo { name: None, spot, direction: IoDirection::Output, config };
+    set_io_pin(&mut bs, &chipdb, &io).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(5, 0)), [(10, 16), (13, 17), (14, 16)]);
+    assert_eq!(set_bits(&bs, TilePos(6, 0)), []);
+  }
+
+  #[test]
+  fn bidirectional_pins_are_inputs_and_outputs() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".io_tile_bits 18 16\n",
+      "IOB_0.PINTYPE_0 B3[17]\n",
+      "IOB_0.PINTYPE_3 B0[16]\n",
+      "IOB_0.PINTYPE_4 B4[16]\n",
+      "IOB_0.PINTYPE_5 B5[16]\n",
+      "IoCtrl.IE_0 B6[3]\n",
+      "IoCtrl.REN_0 B1[3]\n",
+    )).unwrap();
+    let spot = IoPinSpot { tile: TilePos(5, 0), which: 0 };
+    // Without an output enable the pin is always driven, and with one it's tristated; either way its input is on.
+    for (output_enable, pin_type_bits) in [(OutputEnable::Always, (4, 16)), (OutputEnable::Signal, (5, 16))] {
+      let mut bs = parse(&format!(".io_tile 5 0\n{}", "000000000000000000\n".repeat(16))).unwrap();
+      let config = IoConfig { output_enable, pull_up: true, ..IoConfig::default() };
+      let io = UsedIo { name: None, spot, direction: IoDirection::Bidirectional, config };
+      set_io_pin(&mut bs, &chipdb, &io).unwrap();
+      let mut expected = vec![(0, 16), (3, 17), (6, 3), pin_type_bits];
+      expected.sort();
+      assert_eq!(set_bits(&bs, TilePos(5, 0)), expected);
+    }
+  }
+
+  #[test]
+  fn ram_data_is_written_in_hex() {
+    let mut bs = parse(".device 5k\n").unwrap();
+    let mut words = vec![0; 256];
+    words[0] = 0x1234;
+    words[15] = 0xabcd;
+    words[16] = 0x0001;
+    bs.set_ram_data(TilePos(8, 1), &words);
+    let mut s = String::new();
+    serialize(&bs, &mut s).unwrap();
+    let lines: Vec<&str> = s.lines().collect();
+    assert_eq!(lines[1], ".ram_data 8 1");
+    // Each row is 16 words, with the first word last.
+    assert_eq!(lines[2], format!("abcd{}1234", "0000".repeat(14)));
+    assert_eq!(lines[3], format!("{}0001", "0000".repeat(15)));
+    assert_eq!(lines[2..].iter().filter(|line| !line.is_empty()).count(), 16);
+    // And it reads back the same.
+    let mut again = String::new();
+    serialize(&parse(&s).unwrap(), &mut again).unwrap();
+    assert_eq!(again, s);
+  }
+
+  #[test]
+  fn dsp_parameters_set_one_bit_per_bit() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".dsp0_tile 0 5\n",
+      ".dsp0_tile_bits 54 16\n",
+      "IpConfig.CBIT_0 B0[7]\n",
+      "IpConfig.CBIT_1 B1[7]\n",
+      "IpConfig.CBIT_2 B2[7]\n",
+      ".extra_cell 0 5 MAC16\n",
+      "A_SIGNED 0 5 CBIT_0\n",
+      "TOPOUTPUT_SELECT_0 0 5 CBIT_1\n",
+      "TOPOUTPUT_SELECT_1 0 5 CBIT_2\n",
+      "CLK 0 5 mult/clk\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\nwires: []\n",
+      "dsps:\n  - {parameters: {A_SIGNED: 1, TOPOUTPUT_SELECT: 2}}\n",
+    )).unwrap();
+    let solution = PnrSolution { lut_placements: vec![], ram_placements: vec![], dsp_placements: vec![0], spram_placements: vec![], pll_placements: vec![], hard_ip_placements: vec![], configured_arcs: vec![] };
+    let mut bs = parse(&format!(".dsp0_tile 0 5\n{}", format!("{}\n", "0".repeat(54)).repeat(16))).unwrap();
+    add_dsps(&mut bs, &chipdb, &problem, &solution).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(0, 5)), [(0, 7), (2, 7)]);
+    // Ports aren't configuration bits.
+    assert!(set_extra_cell_bit(&mut bs, &chipdb, &chipdb.extra_cells[0], "CLK").is_err());
+  }
+
+  #[test]
+  fn sprams_are_enabled_by_their_extra_cell_bit() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".ipcon_tile 0 1\n",
+      ".ipcon_tile_bits 42 16\n",
+      "IpConfig.CBIT_0 B0[2]\n",
+      "IpConfig.CBIT_1 B1[2]\n",
+      ".extra_cell 0 0 1 SPRAM\n",
+      "SPRAM_EN 0 1 CBIT_0\n",
+      ".extra_cell 0 0 2 SPRAM\n",
+      "SPRAM_EN 0 1 CBIT_1\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str("used_ios: []\nlut4s: []\nwires: []\nsprams:\n  - {}\n").unwrap();
+    let solution = PnrSolution { lut_placements: vec![], ram_placements: vec![], dsp_placements: vec![], spram_placements: vec![1], pll_placements: vec![], hard_ip_placements: vec![], configured_arcs: vec![] };
+    let mut bs = parse(&format!(".ipcon_tile 0 1\n{}", format!("{}\n", "0".repeat(42)).repeat(16))).unwrap();
+    add_sprams(&mut bs, &chipdb, &problem, &solution).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(0, 1)), [(1, 2)]);
+  }
+
+  #[test]
+  fn oscillator_dividers() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".ipcon_tile 0 1\n",
+      ".ipcon_tile_bits 42 16\n",
+      "IpConfig.CBIT_3 B3[2]\n",
+      "IpConfig.CBIT_4 B4[2]\n",
+      ".extra_cell 0 0 1 HFOSC\n",
+      "CLKHF_DIV_0 0 1 CBIT_3\n",
+      "CLKHF_DIV_1 0 1 CBIT_4\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\n",
+      "wires:\n  - {from: {type: HfOsc, divider: 2}, to: {type: Clock, lut_index: 0}}\n",
+    )).unwrap();
+    let mut bs = parse(&format!(".ipcon_tile 0 1\n{}", format!("{}\n", "0".repeat(42)).repeat(16))).unwrap();
+    add_oscillators(&mut bs, &chipdb, &problem).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(0, 1)), [(4, 2)]);
+  }
+
+  #[test]
+  fn pll_parameters_use_pllconfig_bits() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".io_tile 1 0\n",
+      ".io_tile_bits 18 16\n",
+      "PLL.PLLCONFIG_1 B0[0]\n",
+      "PLL.PLLCONFIG_2 B0[1]\n",
+      "PLL.PLLCONFIG_3 B0[2]\n",
+      "PLL.PLLCONFIG_4 B0[3]\n",
+      "PLL.PLLCONFIG_5 B0[4]\n",
+      "PLL.PLLCONFIG_6 B0[5]\n",
+      "PLL.PLLCONFIG_7 B0[6]\n",
+      ".extra_cell 1 0 PLL\n",
+      "PLLTYPE_0 1 0 PLLCONFIG_1\n",
+      "PLLTYPE_1 1 0 PLLCONFIG_2\n",
+      "FEEDBACK_PATH_0 1 0 PLLCONFIG_3\n",
+      "DIVR_0 1 0 PLLCONFIG_4\n",
+      "DIVF_1 1 0 PLLCONFIG_5\n",
+      "DIVQ_0 1 0 PLLCONFIG_6\n",
+      "FILTER_RANGE_0 1 0 PLLCONFIG_7\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\nwires: []\n",
+      "plls:\n  - {divr: 1, divf: 2, divq: 1, filter_range: 1}\n",
+    )).unwrap();
+    let solution = PnrSolution { lut_placements: vec![], ram_placements: vec![], dsp_placements: vec![], spram_placements: vec![], pll_placements: vec![0], hard_ip_placements: vec![], configured_arcs: vec![] };
+    let mut bs = parse(&format!(".io_tile 1 0\n{}", "000000000000000000\n".repeat(16))).unwrap();
+    add_plls(&mut bs, &chipdb, &problem, &solution).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(1, 0)), (0..7).map(|col| (0, col)).collect::<Vec<_>>());
+    // A divider too big for the chipdb's bits is an error rather than silently dropped.
+    let problem: PnrProblem = serde_yaml::from_str("used_ios: []\nlut4s: []\nwires: []\nplls:\n  - {divr: 2, divf: 2, divq: 1, filter_range: 1}\n").unwrap();
+    let mut bs = parse(&format!(".io_tile 1 0\n{}", "000000000000000000\n".repeat(16))).unwrap();
+    assert!(add_plls(&mut bs, &chipdb, &problem, &solution).is_err());
+  }
+
+  #[test]
+  fn rgb_driver_currents() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".ipcon_tile 0 1\n",
+      ".ipcon_tile_bits 42 16\n",
+      "IpConfig.CBIT_0 B0[9]\n",
+      "IpConfig.CBIT_1 B1[9]\n",
+      "IpConfig.CBIT_2 B2[9]\n",
+      "IpConfig.CBIT_3 B3[9]\n",
+      ".extra_cell 0 0 2 RGBA_DRV\n",
+      "RGBA_DRV_EN 0 1 CBIT_0\n",
+      "CURRENT_MODE 0 1 CBIT_1\n",
+      "RGB0_CURRENT_0 0 1 CBIT_2\n",
+      "RGB2_CURRENT_0 0 1 CBIT_3\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str("used_ios: []\nlut4s: []\nwires: []\nrgb_driver: {current_mode: half, rgb2_current: 1}\n").unwrap();
+    let mut bs = parse(&format!(".ipcon_tile 0 1\n{}", format!("{}\n", "0".repeat(42)).repeat(16))).unwrap();
+    add_rgb_driver(&mut bs, &chipdb, &problem).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(0, 1)), [(0, 9), (1, 9), (3, 9)]);
+  }
+
+  #[test]
+  fn hard_ips_set_their_enable_bits() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".io_tile 0 31\n",
+      ".io_tile_bits 18 16\n",
+      "IoCtrl.cbit2usealt_in_0 B12[3]\n",
+      "IoCtrl.cbit2usealt_in_1 B12[4]\n",
+      ".ipcon_tile 0 30\n",
+      ".ipcon_tile_bits 42 16\n",
+      "IpConfig.CBIT_5 B5[1]\n",
+      ".extra_cell 0 0 31 I2C\n",
+      "I2C_ENABLE_0 0 31 cbit2usealt_in_0\n",
+      "I2C_ENABLE_1 0 31 cbit2usealt_in_1\n",
+      "SDA_INPUT_DELAYED 0 30 CBIT_5\n",
+      "SBCLKI 0 30 lutff_1/in_1\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\nwires: []\n",
+      "hard_ips:\n  - {cell_type: I2C, config: [SDA_INPUT_DELAYED]}\n",
+    )).unwrap();
+    let solution = PnrSolution { lut_placements: vec![], ram_placements: vec![], dsp_placements: vec![], spram_placements: vec![], pll_placements: vec![], hard_ip_placements: vec![0], configured_arcs: vec![] };
+    let mut bs = parse(&format!(
+      ".io_tile 0 31\n{}.ipcon_tile 0 30\n{}",
+      "000000000000000000\n".repeat(16),
+      format!("{}\n", "0".repeat(42)).repeat(16),
+    )).unwrap();
+    add_hard_ips(&mut bs, &chipdb, &problem, &solution).unwrap();
+    assert_eq!(set_bits(&bs, TilePos(0, 31)), [(12, 3), (12, 4)]);
+    assert_eq!(set_bits(&bs, TilePos(0, 30)), [(5, 1)]);
+    // Ports aren't configuration bits.
+    assert!(set_extra_cell_bit(&mut bs, &chipdb, &chipdb.extra_cells[0], "SBCLKI").is_err());
+  }
+
+  #[test]
+  fn io_pins_on_the_1k_have_active_low_input_enables_and_pull_ups() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 1k\n",
+      ".io_tile_bits 18 16\n",
+      "IOB_0.PINTYPE_0 B0[17]\n",
+      "IOB_0.PINTYPE_1 B1[17]\n",
+      "IOB_0.PINTYPE_2 B2[17]\n",
+      "IOB_0.PINTYPE_3 B3[17]\n",
+      "IOB_0.PINTYPE_4 B4[17]\n",
+      "IOB_0.PINTYPE_5 B5[17]\n",
+      "IoCtrl.IE_0 B9[3]\n",
+      "IoCtrl.REN_0 B6[3]\n",
+    )).unwrap();
+    let io_bits = |direction: IoDirection, pull_up: bool| {
+      let mut bs = parse(&format!(".io_tile 1 0\n{}", "000000000000000000\n".repeat(16))).unwrap();
+      let config = IoConfig { pull_up, ..IoConfig::default() };
+      let io = UsedIo { name: None, spot: IoPinSpot { tile: TilePos(1, 0), which: 0 }, direction, config };
+      set_io_pin(&mut bs, &chipdb, &io).unwrap();
+      set_bits(&bs, TilePos(1, 0)).into_iter().filter(|&(_, col)| col == 3).collect::<Vec<_>>()
+    };
+    // An input with a pull-up leaves IE clear and sets REN.
+    assert_eq!(io_bits(IoDirection::Input, true), [(6, 3)]);
+    // An output without one sets IE to turn the input off, and leaves REN clear.
+    assert_eq!(io_bits(IoDirection::Output, false), [(9, 3)]);
+  }
+
+  #[test]
+  fn empty_bitstreams_turn_on_the_column_buffers() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 1k\n",
+      ".io_tile 1 0\n",
+      ".logic_tile 1 1\n",
+      ".logic_tile 1 2\n",
+      ".io_tile_bits 18 16\n",
+      ".logic_tile_bits 54 16\n",
+      "ColBufCtrl.glb_netwk_0 B0[7]\n",
+      "ColBufCtrl.glb_netwk_1 B1[7]\n",
+      "ColBufCtrl.glb_netwk_2 B2[7]\n",
+      "ColBufCtrl.glb_netwk_3 B3[7]\n",
+      "ColBufCtrl.glb_netwk_4 B4[7]\n",
+      "ColBufCtrl.glb_netwk_5 B5[7]\n",
+      "ColBufCtrl.glb_netwk_6 B6[7]\n",
+      "ColBufCtrl.glb_netwk_7 B7[7]\n",
+      ".colbuf\n",
+      "1 2 1 1\n",
+      "1 2 1 2\n",
+    )).unwrap();
+    let bs = empty(&chipdb).unwrap();
+    assert_eq!(bs.device(), Some("1k"));
+    assert_eq!(bs.entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), ["comment", "device", "io_tile", "logic_tile", "logic_tile"]);
+    assert_eq!(set_bits(&bs, TilePos(1, 0)), []);
+    assert_eq!(set_bits(&bs, TilePos(1, 1)), []);
+    assert_eq!(set_bits(&bs, TilePos(1, 2)), (0..8).map(|row| (row, 7)).collect::<Vec<_>>());
+    // It goes through the usual .asc format.
+    let mut s = String::new();
+    serialize(&bs, &mut s).unwrap();
+    assert!(s.starts_with(".comment from ice40pnr\n.device 1k\n.io_tile 1 0\n"), "{}", s);
+    assert_eq!(set_bits(&parse(&s).unwrap(), TilePos(1, 2)).len(), 8);
+  }
+
+  #[test]
+  fn designs_go_on_top_of_a_non_empty_base() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".logic_tile_bits 54 16\n",
+      "CarryInSet B1[50]\n",
+      "NegClk !B0[0] B0[1]\n",
+    )).unwrap();
+    let mut tile = vec!["0".repeat(54); 16];
+    tile[0] = format!("1{}", "0".repeat(53));
+    tile[1] = format!("{}1{}", "0".repeat(50), "000");
+    let mut bs = parse(&format!(".logic_tile 1 1\n{}\n", tile.join("\n"))).unwrap();
+    // A bit that's already set in the base is fine, and the bits that must be clear are cleared.
+    bs.set_named_bit(&chipdb, "logic", TilePos(1, 1), "CarryInSet").unwrap();
+    bs.set_named_bit(&chipdb, "logic", TilePos(1, 1), "NegClk").unwrap();
+    assert_eq!(set_bits(&bs, TilePos(1, 1)), [(0, 1), (1, 50)]);
+  }
+
+  #[test]
+  #[should_panic(expected = "Bit [1, 50] of tile TilePos(1, 1) is already set")]
+  fn designs_may_not_set_a_bit_twice() {
+    let chipdb = ChipDb::parse(".device 5k\n.logic_tile_bits 54 16\nCarryInSet B1[50]\n").unwrap();
+    let mut bs = parse(&format!(".logic_tile 1 1\n{}", format!("{}\n", "0".repeat(54)).repeat(16))).unwrap();
+    bs.set_named_bit(&chipdb, "logic", TilePos(1, 1), "CarryInSet").unwrap();
+    bs.set_named_bit(&chipdb, "logic", TilePos(1, 1), "CarryInSet").unwrap();
+  }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use serde::Deserialize;
 
-use crate::pnr::IoPinSpot;
+use crate::pnr::{IoPinSpot, RamPin};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChipNetIndex(pub usize);
@@ -49,6 +49,42 @@ pub struct Connection {
   pub source: ChipNetIndex,
 }
 
+/// A hard IP block, like a DSP, from an .extra_cell record of the chipdb. Each entry maps a port of the cell to a net,
+/// or one of the cell's configuration bits to a "CBIT_<n>" (or for a PLL, "PLLCONFIG_<n>") bit, in some tile.
+/// The PLL's outputs are given as the number of the IO pad they come out of instead of a net.
+#[derive(Debug, Clone)]
+pub struct ExtraCell {
+  pub cell_type: String,
+  pub tile: TilePos,
+  pub z: u8,
+  pub entries: HashMap<String, (TilePos, String)>,
+}
+
+impl ExtraCell {
+  pub fn describe(&self) -> String {
+    format!("{} at {:?}/{}", self.cell_type, self.tile, self.z)
+  }
+
+  pub fn is_config_bit(name: &str) -> bool {
+    name.starts_with("CBIT_") || name.starts_with("PLLCONFIG_")
+  }
+
+  /// Whether an entry turns the cell on, like I2C_ENABLE_0 of an I2C block.
+  pub fn is_enable_bit(key: &str) -> bool {
+    key.contains("_ENABLE_")
+  }
+
+  pub fn has_port(&self, port: &str) -> bool {
+    !Self::is_enable_bit(port) && self.entries.get(port).is_some_and(|(_, name)| !Self::is_config_bit(name))
+  }
+
+  /// The IO pad a port comes out of, for ports like PLLOUT_A of the PLL.
+  pub fn port_pad(&self, port: &str) -> Option<IoPinSpot> {
+    let (tile, name) = self.entries.get(port)?;
+    Some(IoPinSpot { tile: *tile, which: name.parse().ok()? })
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct PackagePins {
   pub pin_name_to_pos_and_index: HashMap<String, (TilePos, u8)>,
@@ -56,24 +92,59 @@ pub struct PackagePins {
 
 #[derive(Debug)]
 pub struct ChipDb {
+  /// The device the chipdb describes, as its .device line gives it, like "1k" or "5k".
+  pub device: String,
   pub nets: Vec<ChipNetEntry>,
   pub arcs: Vec<ArcEntry>,
   pub froms: HashMap<ChipNetIndex, Vec<(ChipNetIndex, ConfiguredArc)>>,
   pub net_by_name: HashMap<(TilePos, String), ChipNetIndex>,
   pub logic_tiles: Vec<TilePos>,
   pub pins_by_package: HashMap<String, PackagePins>,
+  /// The tile whose fabout drives each global network.
+  pub global_net_ingress_tiles: HashMap<u8, TilePos>,
+  /// The IO pins with a dedicated connection to a global network.
+  pub global_net_pins: HashMap<IoPinSpot, u8>,
+  /// The configuration bits of each kind of tile (like "io" or "logic"), by function name (like "IOB_0.PINTYPE_0").
+  pub tile_bits: HashMap<String, HashMap<String, Vec<String>>>,
+  /// The columns and rows of bits of each kind of tile, from its .<type>_tile_bits line.
+  pub tile_bit_sizes: HashMap<String, (usize, usize)>,
+  /// Where the input enable and pull-up bits of each IO pin live, which isn't always the pin's own spot.
+  pub ieren: HashMap<IoPinSpot, IoPinSpot>,
+  /// The bottom tile of each block RAM. Every block RAM takes up this tile and the one above it.
+  pub ram_tiles: Vec<TilePos>,
+  /// The kind of every tile, like "logic", "io" or "dsp0", which says where to find its bits in tile_bits.
+  pub tile_types: HashMap<TilePos, String>,
+  /// The tiles whose ColBufCtrl bits pass the global networks on to their part of a column, from the .colbuf section.
+  pub column_buffer_tiles: Vec<TilePos>,
+  pub extra_cells: Vec<ExtraCell>,
 }
 
 impl ChipDb {
   pub fn parse(content: &str) -> Result<Self, String> {
+    let mut device = None;
     let mut nets = Vec::new();
     let mut arcs = Vec::new();
     let mut logic_tiles = Vec::new();
     let mut pins_by_package = HashMap::new();
+    let mut global_net_ingress_tiles = HashMap::new();
+    let mut global_net_pins = HashMap::new();
+    let mut tile_bits: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+    let mut tile_bit_sizes = HashMap::new();
+    let mut ieren = HashMap::new();
+    let mut ram_tiles = Vec::new();
+    let mut tile_types = HashMap::new();
+    let mut column_buffer_tiles = Vec::new();
+    let mut extra_cells = Vec::new();
     let mut lines = content.lines();
 
     enum State {
       Pins(String, PackagePins),
+      GbufIn,
+      GbufPin,
+      TileBits(String),
+      IeRen,
+      ColBuf,
+      ExtraCell(ExtraCell),
       Net(ChipNetEntry),
       Arc(ArcEntry),
     }
@@ -92,7 +163,10 @@ impl ChipDb {
           Some(State::Arc(arc)) => {
             arcs.push(arc);
           }
-          None => {}
+          Some(State::ExtraCell(extra_cell)) => {
+            extra_cells.push(extra_cell);
+          }
+          Some(State::GbufIn | State::GbufPin | State::TileBits(_) | State::IeRen | State::ColBuf) | None => {}
         }
       };
     }
@@ -106,6 +180,10 @@ impl ChipDb {
       let mut line_chunks = line.split_whitespace();
       let first = line_chunks.next().unwrap();
       match first {
+        ".device" => {
+          finish_state!();
+          device = line_chunks.next().map(|name| name.to_string());
+        }
         ".pins" => {
           finish_state!();
           let package = line_chunks.next().unwrap().to_string();
@@ -113,12 +191,71 @@ impl ChipDb {
             pin_name_to_pos_and_index: HashMap::new(),
           }));
         }
+        ".gbufin" => {
+          finish_state!();
+          state = Some(State::GbufIn);
+        }
+        ".gbufpin" => {
+          finish_state!();
+          state = Some(State::GbufPin);
+        }
+        ".ieren" => {
+          finish_state!();
+          state = Some(State::IeRen);
+        }
+        ".colbuf" => {
+          finish_state!();
+          state = Some(State::ColBuf);
+        }
+        x if x.ends_with("_tile_bits") => {
+          finish_state!();
+          let tile_type = x[1..].trim_end_matches("_tile_bits").to_string();
+          tile_bits.entry(tile_type.clone()).or_default();
+          if let (Some(cols), Some(rows)) = (line_chunks.next(), line_chunks.next()) {
+            let parse = |s: &str| s.parse().map_err(|_| format!("Invalid tile bits line: {}", line));
+            tile_bit_sizes.insert(tile_type.clone(), (parse(cols)?, parse(rows)?));
+          }
+          state = Some(State::TileBits(tile_type));
+        }
         ".logic_tile" => {
           finish_state!();
           let x = line_chunks.next().unwrap().parse().unwrap();
           let y = line_chunks.next().unwrap().parse().unwrap();
           assert!(line_chunks.next().is_none());
           logic_tiles.push(TilePos(x, y));
+          tile_types.insert(TilePos(x, y), "logic".to_string());
+        }
+        ".ramb_tile" => {
+          finish_state!();
+          let x = line_chunks.next().unwrap().parse().unwrap();
+          let y = line_chunks.next().unwrap().parse().unwrap();
+          assert!(line_chunks.next().is_none());
+          ram_tiles.push(TilePos(x, y));
+          tile_types.insert(TilePos(x, y), "ramb".to_string());
+        }
+        x if x.ends_with("_tile") => {
+          finish_state!();
+          let x_pos = line_chunks.next().unwrap().parse().unwrap();
+          let y_pos = line_chunks.next().unwrap().parse().unwrap();
+          assert!(line_chunks.next().is_none());
+          tile_types.insert(TilePos(x_pos, y_pos), x[1..].trim_end_matches("_tile").to_string());
+        }
+        ".extra_cell" => {
+          finish_state!();
+          // Either ".extra_cell <x> <y> <z> <type>", or ".extra_cell <x> <y> <type>" for a cell that is alone in its tile.
+          let chunks: Vec<&str> = line_chunks.collect();
+          let (x, y, z, cell_type) = match chunks[..] {
+            [x, y, z, cell_type] => (x, y, z, cell_type),
+            [x, y, cell_type] => (x, y, "0", cell_type),
+            _ => return Err(format!("Invalid .extra_cell line: {}", line)),
+          };
+          let parse = |s: &str| s.parse().map_err(|_| format!("Invalid .extra_cell line: {}", line));
+          state = Some(State::ExtraCell(ExtraCell {
+            cell_type: cell_type.to_string(),
+            tile: TilePos(parse(x)?, parse(y)?),
+            z: parse(z)?,
+            entries: HashMap::new(),
+          }));
         }
         ".net" => {
           finish_state!();
@@ -160,6 +297,56 @@ impl ChipDb {
               return Err(format!("Duplicate pin name: {}", first));
             }
           }
+          Some(State::GbufIn) => {
+            let x = first.parse().unwrap();
+            let y = line_chunks.next().unwrap().parse().unwrap();
+            let global_net_index = line_chunks.next().unwrap().parse().unwrap();
+            assert!(line_chunks.next().is_none());
+            if global_net_ingress_tiles.insert(global_net_index, TilePos(x, y)).is_some() {
+              return Err(format!("Duplicate .gbufin entry for global network {}", global_net_index));
+            }
+          }
+          Some(State::GbufPin) => {
+            let x = first.parse().unwrap();
+            let y = line_chunks.next().unwrap().parse().unwrap();
+            let which = line_chunks.next().unwrap().parse().unwrap();
+            let global_net_index = line_chunks.next().unwrap().parse().unwrap();
+            assert!(line_chunks.next().is_none());
+            global_net_pins.insert(IoPinSpot { tile: TilePos(x, y), which }, global_net_index);
+          }
+          Some(State::TileBits(tile_type)) => {
+            let bits = line_chunks.map(|s| s.to_string()).collect();
+            tile_bits.get_mut(tile_type).unwrap().insert(first.to_string(), bits);
+          }
+          Some(State::IeRen) => {
+            let numbers: Vec<u8> = std::iter::once(first).chain(line_chunks).map(|s| s.parse().unwrap()).collect();
+            let [x, y, which, ie_x, ie_y, ie_which] = numbers[..] else {
+              return Err(format!("Invalid .ieren line: {}", line));
+            };
+            ieren.insert(
+              IoPinSpot { tile: TilePos(x, y), which },
+              IoPinSpot { tile: TilePos(ie_x, ie_y), which: ie_which },
+            );
+          }
+          Some(State::ColBuf) => {
+            // Each line is "<colbuf x> <colbuf y> <x> <y>", for every tile that the column buffer tile feeds.
+            let numbers: Vec<u8> = std::iter::once(first).chain(line_chunks).map(|s| s.parse().unwrap()).collect();
+            let [x, y, _, _] = numbers[..] else {
+              return Err(format!("Invalid .colbuf line: {}", line));
+            };
+            if !column_buffer_tiles.contains(&TilePos(x, y)) {
+              column_buffer_tiles.push(TilePos(x, y));
+            }
+          }
+          Some(State::ExtraCell(extra_cell)) => {
+            // Besides ports and configuration bits there are other entries, like LOCKED, which we don't need.
+            let chunks: Vec<&str> = line_chunks.collect();
+            if let [x, y, name] = chunks[..]
+              && let (Ok(x), Ok(y)) = (x.parse(), y.parse())
+            {
+              extra_cell.entries.insert(first.to_string(), (TilePos(x, y), name.to_string()));
+            }
+          }
           Some(State::Net(net)) => {
             let x = first.parse().unwrap();
             let y = line_chunks.next().unwrap().parse().unwrap();
@@ -205,13 +392,47 @@ impl ChipDb {
       }
     }
 
-    Ok(ChipDb { nets, arcs, froms, net_by_name, logic_tiles, pins_by_package })
+    Ok(ChipDb {
+      device: device.ok_or("The chipdb has no .device line")?,
+      nets,
+      arcs,
+      froms,
+      net_by_name,
+      logic_tiles,
+      pins_by_package,
+      global_net_ingress_tiles,
+      global_net_pins,
+      tile_bits,
+      tile_bit_sizes,
+      ieren,
+      ram_tiles,
+      tile_types,
+      column_buffer_tiles,
+      extra_cells,
+    })
   }
 
-  pub fn get_io_pin_spot(&self, package: &str, pin_name: &str) -> IoPinSpot {
-    let pins = &self.pins_by_package[package].pin_name_to_pos_and_index;
-    let &(tile, which) = pins.get(pin_name).unwrap();
-    IoPinSpot { tile, which }
+  pub fn get_io_pin_spot(&self, package: &str, pin_name: &str) -> Result<IoPinSpot, String> {
+    let Some(package_pins) = self.pins_by_package.get(package) else {
+      let mut packages: Vec<_> = self.pins_by_package.keys().map(|p| p.as_str()).collect();
+      packages.sort();
+      return Err(format!("Unknown package {:?} (available packages: {})", package, packages.join(", ")));
+    };
+    let Some(&(tile, which)) = package_pins.pin_name_to_pos_and_index.get(pin_name) else {
+      let mut other_packages: Vec<_> = self.pins_by_package.iter()
+        .filter(|(_, pins)| pins.pin_name_to_pos_and_index.contains_key(pin_name))
+        .map(|(p, _)| p.as_str())
+        .collect();
+      other_packages.sort();
+      return Err(match other_packages.is_empty() {
+        true => format!("Unknown pin {:?}", pin_name),
+        false => format!(
+          "Pin {:?} does not exist in package {} (it exists in: {})",
+          pin_name, package, other_packages.join(", "),
+        ),
+      });
+    };
+    Ok(IoPinSpot { tile, which })
   }
 
   pub fn ff_out(&self, tile: TilePos, lut_number: u8) -> Result<ChipNetIndex, String> {
@@ -233,6 +454,23 @@ impl ChipDb {
     self.get_net_by_name(tile, &format!("io_{}/D_OUT_0", which))
   }
 
+  /// The net for a pin of the block RAM whose bottom tile is `tile`. The write port is in the bottom
+  /// tile on some chips (like the 1k) and in the top tile on others (like the 5k and 8k), with the read port in the other.
+  pub fn ram_net(&self, tile: TilePos, pin: RamPin) -> Result<ChipNetIndex, String> {
+    let top = TilePos(tile.0, tile.1 + 1);
+    let (write_tile, read_tile) = match self.net_by_name.contains_key(&(tile, "ram/WE".to_string())) {
+      true => (tile, top),
+      false => (top, tile),
+    };
+    let pin_tile = match pin {
+      // The low byte of each data bus sits with the write port, and the high byte with the read port.
+      RamPin::ReadData(i) | RamPin::WriteData(i) | RamPin::Mask(i) => if i < 8 { write_tile } else { read_tile },
+      RamPin::WriteAddress(_) | RamPin::WriteClock | RamPin::WriteClockEnable | RamPin::WriteEnable => write_tile,
+      RamPin::ReadAddress(_) | RamPin::ReadClock | RamPin::ReadClockEnable | RamPin::ReadEnable => read_tile,
+    };
+    self.get_net_by_name(pin_tile, &format!("ram/{}", pin))
+  }
+
   pub fn get_net_by_name(&self, tile: TilePos, name: &str) -> Result<ChipNetIndex, String> {
     let k = (tile, name.to_string());
     self.net_by_name.get(&k).copied().ok_or_else(|| format!("Net not found: {:?}", k))
@@ -247,10 +485,182 @@ impl ChipDb {
     None
   }
 
+  pub fn get_global_net_ingress_tile(&self, global_net_index: u8) -> Result<TilePos, String> {
+    match self.global_net_ingress_tiles.get(&global_net_index) {
+      Some(&tile) => Ok(tile),
+      None if global_net_index < 8 => Err(format!("Global network {} has no ingress tile in the chipdb", global_net_index)),
+      None => Err(format!("Global net index out of range: {}", global_net_index)),
+    }
+  }
+
+  pub fn get_tile_bits(&self, tile_type: &str, name: &str) -> Result<&[String], String> {
+    self.tile_bits.get(tile_type)
+      .and_then(|bits| bits.get(name))
+      .map(|bits| &bits[..])
+      .ok_or_else(|| format!("No {} tile bit named {} in the chipdb", tile_type, name))
+  }
+
+  /// The full name of a tile bit that's given by its last part, like "cbit2usealt_in_0" for "IoCtrl.cbit2usealt_in_0".
+  pub fn find_tile_bit(&self, tile_type: &str, name: &str) -> Option<&str> {
+    let suffix = format!(".{}", name);
+    let bits = self.tile_bits.get(tile_type)?;
+    bits.keys().map(|bit| bit.as_str()).find(|&bit| bit == name || bit.ends_with(&suffix))
+  }
+
+  pub fn get_tile_type(&self, tile: TilePos) -> Result<&str, String> {
+    self.tile_types.get(&tile).map(|t| t.as_str()).ok_or_else(|| format!("No tile at {:?}", tile))
+  }
+
+  /// The net of a port of an extra cell, like "A_3" of a MAC16.
+  pub fn extra_cell_net(&self, extra_cell: &ExtraCell, port: &str) -> Result<ChipNetIndex, String> {
+    match extra_cell.entries.get(port) {
+      // A port that comes out of an IO pad takes the place of the pad's input.
+      Some(_) if let Some(spot) = extra_cell.port_pad(port) => self.io_tile_out(spot),
+      Some((tile, name)) if extra_cell.has_port(port) => self.get_net_by_name(*tile, name),
+      _ => Err(format!("{} has no port {}", extra_cell.describe(), port)),
+    }
+  }
+
+  pub fn find_extra_cell(&self, cell_type: &str) -> Result<&ExtraCell, String> {
+    self.extra_cells.iter().find(|cell| cell.cell_type == cell_type).ok_or_else(|| format!("No {} in the chipdb", cell_type))
+  }
+
+  /// The output net of an internal oscillator, like "CLKHF" of the HFOSC. Where the chipdb has both, this is the
+  /// one that feeds the general routing (like "CLKHF_FABRIC") rather than the one wired straight into a global network,
+  /// so that it can be routed to a global network's ingress like any other net.
+  pub fn oscillator_net(&self, cell_type: &str, port: &str) -> Result<ChipNetIndex, String> {
+    let cell = self.find_extra_cell(cell_type)?;
+    let fabric_port = format!("{}_FABRIC", port);
+    self.extra_cell_net(cell, if cell.has_port(&fabric_port) { &fabric_port } else { port })
+  }
+
+  pub fn get_global_net(&self, global_net_index: u8) -> Result<ChipNetIndex, String> {
+    // A global network reaches every logic tile, so any of them will do for looking it up.
+    let &tile = self.logic_tiles.first().ok_or("No logic tiles in the chipdb")?;
+    self.get_net_by_name(tile, &format!("glb_netwk_{}", global_net_index))
+  }
+
   pub fn get_global_net_ingress_point(&self, global_net_index: u8) -> Result<ChipNetIndex, String> {
-    match global_net_index {
-      7 => self.get_net_by_name(TilePos(19, 0), "fabout"),
-      _ => Err(format!("Global net index out of range: {}", global_net_index)),
+    self.get_net_by_name(self.get_global_net_ingress_tile(global_net_index)?, "fabout")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn global_networks() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".gbufin\n19 0 7\n6 0 5\n",
+      ".gbufpin\n6 0 1 5\n",
+      ".net 0\n19 0 fabout\n",
+    )).unwrap();
+    assert_eq!(chipdb.get_global_net_ingress_tile(7), Ok(TilePos(19, 0)));
+    assert_eq!(chipdb.get_global_net_ingress_tile(5), Ok(TilePos(6, 0)));
+    assert!(chipdb.get_global_net_ingress_tile(0).is_err());
+    assert_eq!(chipdb.get_global_net_ingress_point(7), Ok(ChipNetIndex(0)));
+    assert_eq!(chipdb.global_net_pins.get(&IoPinSpot { tile: TilePos(6, 0), which: 1 }), Some(&5));
+    assert!(ChipDb::parse(".device 5k\n.gbufin\n19 0 7\n6 0 7\n").is_err());
+  }
+
+  #[test]
+  fn ram_pins_split_between_the_two_tiles() {
+    // On the 5k the write port is in the top tile, and on the 1k in the bottom one.
+    for (write_y, read_y) in [(2, 1), (1, 2)] {
+      let chipdb = ChipDb::parse(&format!(
+        ".device 5k\n.net 0\n8 {0} ram/WE\n.net 1\n8 {0} ram/WDATA_3\n.net 2\n8 {1} ram/WDATA_11\n.net 3\n8 {1} ram/RCLK\n",
+        write_y, read_y,
+      )).unwrap();
+      let tile = TilePos(8, 1);
+      assert_eq!(chipdb.ram_net(tile, RamPin::WriteEnable), Ok(ChipNetIndex(0)));
+      assert_eq!(chipdb.ram_net(tile, RamPin::WriteData(3)), Ok(ChipNetIndex(1)));
+      assert_eq!(chipdb.ram_net(tile, RamPin::WriteData(11)), Ok(ChipNetIndex(2)));
+      assert_eq!(chipdb.ram_net(tile, RamPin::ReadClock), Ok(ChipNetIndex(3)));
     }
   }
+
+  #[test]
+  fn extra_cells() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".extra_cell 0 5 MAC16\n",
+      "A_SIGNED 0 5 CBIT_0\n",
+      "CLK 0 5 mult/clk\n",
+      "LOCKED 0 5\n",
+      ".extra_cell 0 0 1 SPRAM\n",
+      "CLOCK 0 1 spram/clk\n",
+      ".net 0\n0 5 mult/clk\n",
+    )).unwrap();
+    let [mac16, spram] = &chipdb.extra_cells[..] else { panic!("{:?}", chipdb.extra_cells) };
+    assert_eq!((mac16.cell_type.as_str(), mac16.tile, mac16.z), ("MAC16", TilePos(0, 5), 0));
+    assert_eq!((spram.cell_type.as_str(), spram.tile, spram.z), ("SPRAM", TilePos(0, 0), 1));
+    assert!(mac16.has_port("CLK"));
+    assert!(!mac16.has_port("A_SIGNED"));
+    assert!(!mac16.has_port("LOCKED"));
+    assert_eq!(chipdb.extra_cell_net(mac16, "CLK"), Ok(ChipNetIndex(0)));
+    assert!(chipdb.extra_cell_net(mac16, "A_SIGNED").is_err());
+    assert!(ChipDb::parse(".device 5k\n.extra_cell 0 MAC16\n").is_err());
+  }
+
+  #[test]
+  fn oscillators_feed_the_fabric() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".extra_cell 0 0 31 HFOSC\n",
+      "CLKHF 0 31 glb_netwk_4\n",
+      "CLKHF_FABRIC 0 28 slf_op_7\n",
+      ".extra_cell 25 0 31 LFOSC\n",
+      "CLKLF 25 31 glb_netwk_5\n",
+      ".net 0\n0 31 glb_netwk_4\n",
+      ".net 1\n0 28 slf_op_7\n",
+      ".net 2\n25 31 glb_netwk_5\n",
+    )).unwrap();
+    assert_eq!(chipdb.oscillator_net("HFOSC", "CLKHF"), Ok(ChipNetIndex(1)));
+    assert_eq!(chipdb.oscillator_net("LFOSC", "CLKLF"), Ok(ChipNetIndex(2)));
+    assert!(chipdb.find_extra_cell("PLL").is_err());
+  }
+
+  #[test]
+  fn hard_ip_enable_bits() {
+    assert!(ExtraCell::is_enable_bit("I2C_ENABLE_0"));
+    assert!(ExtraCell::is_enable_bit("SPI_ENABLE_1"));
+    assert!(!ExtraCell::is_enable_bit("SBCLKI"));
+    assert!(!ExtraCell::is_enable_bit("SDA_INPUT_DELAYED"));
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".io_tile 0 31\n",
+      ".io_tile_bits 18 16\n",
+      "IoCtrl.cbit2usealt_in_0 B12[3]\n",
+      ".extra_cell 0 0 31 I2C\n",
+      "I2C_ENABLE_0 0 31 cbit2usealt_in_0\n",
+      "SBCLKI 0 30 lutff_1/in_1\n",
+    )).unwrap();
+    let i2c = &chipdb.extra_cells[0];
+    assert!(!i2c.has_port("I2C_ENABLE_0"));
+    assert!(i2c.has_port("SBCLKI"));
+    assert_eq!(chipdb.find_tile_bit("io", "cbit2usealt_in_0"), Some("IoCtrl.cbit2usealt_in_0"));
+    assert_eq!(chipdb.find_tile_bit("io", "usealt_in_0"), None);
+  }
+
+  #[test]
+  fn tile_bit_sizes_and_column_buffers() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 1k\n",
+      ".logic_tile_bits 54 16\n",
+      "ColBufCtrl.glb_netwk_0 B9[7]\n",
+      ".io_tile_bits 18 16\n",
+      ".colbuf\n",
+      "1 4 1 1\n",
+      "1 4 1 2\n",
+      "1 5 1 6\n",
+    )).unwrap();
+    assert_eq!(chipdb.device, "1k");
+    assert_eq!(chipdb.tile_bit_sizes["logic"], (54, 16));
+    assert_eq!(chipdb.tile_bit_sizes["io"], (18, 16));
+    assert_eq!(chipdb.column_buffer_tiles, [TilePos(1, 4), TilePos(1, 5)]);
+    assert!(ChipDb::parse(".device 5k\n.colbuf\n1 4 1\n").is_err());
+    assert!(ChipDb::parse("").is_err());
+  }
 }
@@ -0,0 +1,120 @@
+//! Compiles boolean expressions like `(i0 ^ i1) & !i3` into LUT4 tables.
+//!
+//! Every subexpression is evaluated directly as a 16-entry truth table, where bit `n` of the
+//! table is the value when input `k` is bit `k` of `n`. The operators are, from tightest to
+//! loosest binding: `!` (or `~`), `&`, `^`, `|`. Constants `0` and `1` are also allowed.
+
+/// The truth tables of the four LUT inputs on their own.
+const INPUT_TABLES: [u16; 4] = [0xaaaa, 0xcccc, 0xf0f0, 0xff00];
+
+struct Parser<'a> {
+  expr: &'a str,
+  pos: usize,
+}
+
+impl Parser<'_> {
+  fn peek(&mut self) -> Option<char> {
+    self.expr[self.pos..].chars().find(|c| !c.is_whitespace())
+  }
+
+  fn next(&mut self) -> Option<char> {
+    while let Some(c) = self.expr[self.pos..].chars().next() {
+      self.pos += c.len_utf8();
+      if !c.is_whitespace() {
+        return Some(c);
+      }
+    }
+    None
+  }
+
+  fn binary(&mut self, op: char, operand: fn(&mut Self) -> Result<u16, String>, apply: fn(u16, u16) -> u16) -> Result<u16, String> {
+    let mut table = operand(self)?;
+    while self.peek() == Some(op) {
+      self.next();
+      table = apply(table, operand(self)?);
+    }
+    Ok(table)
+  }
+
+  fn or(&mut self) -> Result<u16, String> {
+    self.binary('|', Self::xor, |a, b| a | b)
+  }
+
+  fn xor(&mut self) -> Result<u16, String> {
+    self.binary('^', Self::and, |a, b| a ^ b)
+  }
+
+  fn and(&mut self) -> Result<u16, String> {
+    self.binary('&', Self::unary, |a, b| a & b)
+  }
+
+  fn unary(&mut self) -> Result<u16, String> {
+    match self.next() {
+      Some('!' | '~') => Ok(!self.unary()?),
+      Some('(') => {
+        let table = self.or()?;
+        match self.next() {
+          Some(')') => Ok(table),
+          _ => Err(format!("Expected ')' at position {}", self.pos)),
+        }
+      }
+      Some('0') => Ok(0x0000),
+      Some('1') => Ok(0xffff),
+      Some(c) if c.is_alphanumeric() || c == '_' => {
+        let start = self.pos - c.len_utf8();
+        while let Some(c) = self.expr[self.pos..].chars().next() && (c.is_alphanumeric() || c == '_') {
+          self.pos += c.len_utf8();
+        }
+        let name = &self.expr[start..self.pos];
+        match name.strip_prefix('i').map(|n| n.parse::<usize>()) {
+          Some(Ok(n)) if n < 4 => Ok(INPUT_TABLES[n]),
+          Some(Ok(_)) => Err(format!("Expression uses input {}, but a LUT4 only has inputs i0 to i3", name)),
+          _ => Err(format!("Unknown input {:?} (LUT inputs are i0 to i3)", name)),
+        }
+      }
+      Some(c) => Err(format!("Unexpected {:?} at position {}", c, self.pos - c.len_utf8())),
+      None => Err("Unexpected end of expression".to_string()),
+    }
+  }
+}
+
+/// Compiles a boolean expression over the inputs i0 to i3 into a LUT4 table.
+pub fn compile(expr: &str) -> Result<u16, String> {
+  let mut parser = Parser { expr, pos: 0 };
+  let table = parser.or()?;
+  match parser.next() {
+    None => Ok(table),
+    Some(c) => Err(format!("Unexpected {:?} at position {}", c, parser.pos - c.len_utf8())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn inputs_and_constants() {
+    assert_eq!(compile("i0"), Ok(0xaaaa));
+    assert_eq!(compile("i3"), Ok(0xff00));
+    assert_eq!(compile("0"), Ok(0x0000));
+    assert_eq!(compile(" 1 "), Ok(0xffff));
+  }
+
+  #[test]
+  fn operators_bind_in_order() {
+    assert_eq!(compile("!i0"), Ok(0x5555));
+    assert_eq!(compile("~(i0 | i1)"), Ok(0x1111));
+    assert_eq!(compile("i0 | i1 & i2"), Ok(0xeaea));
+    assert_eq!(compile("i0 ^ i1 & i2"), Ok(0x6a6a));
+    assert_eq!(compile("(i0 ^ i1) & !i3"), Ok(0x0066));
+  }
+
+  #[test]
+  fn errors() {
+    assert!(compile("i4").is_err());
+    assert!(compile("x").is_err());
+    assert!(compile("(i0").is_err());
+    assert!(compile("i0 i1").is_err());
+    assert!(compile("").is_err());
+  }
+}
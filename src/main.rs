@@ -1,53 +1,245 @@
 pub mod chipdb;
 pub mod pnr;
 pub mod bitstream;
+pub mod yosys;
+pub mod pcf;
+pub mod resolve;
+pub mod expr;
 
-use std::path::PathBuf;
-use clap::Parser;
-use pnr::UsedIo;
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use clap::{Parser, ValueEnum};
+use pnr::IoPinSpot;
 
 /// Simple file processor
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-  /// Input json file to process
+  /// Input file to process: either a YAML PnrProblem, or a Yosys JSON netlist (*.json)
   input_file: PathBuf,
 
   /// Output bitstream file
   #[arg(short, long)]
   output: PathBuf,
+
+  /// Bind a port (or named IO) to an IO pin, as <port>=<x>,<y>,<which>
+  #[arg(long = "pin", value_parser = parse_pin_binding)]
+  pins: Vec<(String, IoPinSpot)>,
+
+  /// Pin constraint file binding ports to package pins with `set_io <port> <pin>` lines
+  #[arg(long, requires = "package")]
+  pcf: Option<PathBuf>,
+
+  /// Package whose pin names the pin constraint file uses (e.g. sg48)
+  #[arg(long)]
+  package: Option<String>,
+
+  /// The iCE40 part to target, which picks the chipdb and the base bitstream
+  #[arg(long, value_enum, default_value_t = Device::Up5k)]
+  device: Device,
+
+  /// Chip database to use instead of the built-in or installed one for the device, either plain or zstd-compressed
+  #[arg(long)]
+  chipdb: Option<PathBuf>,
+
+  /// Bitstream to put the design on top of instead of the built-in empty one, either plain or zstd-compressed
+  #[arg(long)]
+  base_asc: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Device {
+  Hx1k,
+  Hx8k,
+  Lp384,
+  Up5k,
+  U4k,
+}
+
+impl Device {
+  /// The name IceStorm gives the device in .device lines and chipdb file names, like "1k" for chipdb-1k.txt.
+  fn icestorm_name(self) -> &'static str {
+    match self {
+      Device::Hx1k => "1k",
+      Device::Hx8k => "8k",
+      Device::Lp384 => "384",
+      Device::Up5k => "5k",
+      Device::U4k => "u4k",
+    }
+  }
+
+  /// The zstd-compressed chipdb built into the binary, if there's one for this device.
+  fn builtin_chipdb(self) -> Option<&'static [u8]> {
+    match self {
+      Device::Up5k => Some(include_bytes!("../assets/chipdb-5k.txt.zst")),
+      _ => None,
+    }
+  }
+
+  /// The zstd-compressed empty bitstream built into the binary, if there's one for this device.
+  fn builtin_base_asc(self) -> Option<&'static [u8]> {
+    match self {
+      Device::Up5k => Some(include_bytes!("../assets/empty.asc.zst")),
+      _ => None,
+    }
+  }
+}
+
+/// Where IceStorm installs its chipdb files, in the order they're looked for.
+const ICEBOX_DIRS: [&str; 2] = ["/usr/local/share/icebox", "/usr/share/icebox"];
+
+/// Reads the chipdb of a device that doesn't have one built in from an IceStorm installation.
+fn read_installed_chipdb(device: Device) -> Result<String, String> {
+  let file_name = format!("chipdb-{}.txt", device.icestorm_name());
+  for dir in ICEBOX_DIRS {
+    let path = Path::new(dir).join(&file_name);
+    if path.exists() {
+      return read_text_asset(&path);
+    }
+  }
+  Err(format!(
+    "there's no {} built in for the {:?}, nor in {}, so pass one with --chipdb",
+    file_name, device, ICEBOX_DIRS.join(" or "),
+  ))
+}
+
+/// Reads a chipdb or bitstream file, decompressing it if it's zstd-compressed.
+fn read_text_asset(path: &Path) -> Result<String, String> {
+  let bytes = std::fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+  decode_text_asset(bytes)
+}
+
+/// Decompresses a chipdb or bitstream if it starts with the zstd magic number, and otherwise takes it as it is.
+fn decode_text_asset(bytes: Vec<u8>) -> Result<String, String> {
+  let bytes = match bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+    true => zstd::decode_all(&bytes[..]).map_err(|e| format!("can't decompress it: {}", e))?,
+    false => bytes,
+  };
+  String::from_utf8(bytes).map_err(|e| format!("it isn't valid UTF-8: {}", e))
+}
+
+fn parse_pin_binding(s: &str) -> Result<(String, IoPinSpot), String> {
+  let (port, spot) = s.split_once('=').ok_or_else(|| format!("Expected <port>=<x>,<y>,<which>, got {:?}", s))?;
+  let numbers = spot.split(',').map(|n| n.trim().parse::<u8>()).collect::<Result<Vec<_>, _>>();
+  match numbers.as_deref() {
+    Ok(&[x, y, which]) => Ok((port.to_string(), IoPinSpot { tile: chipdb::TilePos(x, y), which })),
+    _ => Err(format!("Expected <port>=<x>,<y>,<which>, got {:?}", s)),
+  }
 }
 
 fn main() {
   let args = Args::parse();
   println!("Args: {:?}", args);
 
+  // Load the chipdb.
+  let data = match (&args.chipdb, args.device.builtin_chipdb()) {
+    (Some(path), _) => read_text_asset(path),
+    (None, Some(builtin)) => decode_text_asset(builtin.to_vec()),
+    (None, None) => read_installed_chipdb(args.device),
+  }.unwrap_or_else(|e| {
+    eprintln!("Error loading the chipdb: {}", e);
+    std::process::exit(1);
+  });
+  let db = chipdb::ChipDb::parse(&data).unwrap_or_else(|e| {
+    eprintln!("Error reading the chipdb: {}", e);
+    std::process::exit(1);
+  });
+  if db.device != args.device.icestorm_name() {
+    eprintln!("Error: the chipdb is for the {} device, not the {}", db.device, args.device.icestorm_name());
+    std::process::exit(1);
+  }
+
+  // Work out which pins the ports are bound to.
+  let mut constraints = pcf::PcfConstraints::default();
+  if let (Some(pcf_path), Some(package)) = (&args.pcf, &args.package) {
+    let pcf_str = std::fs::read_to_string(pcf_path).unwrap();
+    constraints = pcf::parse(&pcf_str, &db, package).unwrap_or_else(|e| {
+      eprintln!("Error reading {}: {}", pcf_path.display(), e);
+      std::process::exit(1);
+    });
+  }
+  let mut port_spots: HashMap<String, IoPinSpot> = constraints.port_spots;
+  // Explicit --pin bindings take precedence over the constraint file.
+  port_spots.extend(args.pins.iter().cloned());
+
   // Load the input.
-  let pnr_problem_str = std::fs::read_to_string(&args.input_file).unwrap();
-  let pnr_problem: pnr::PnrProblem = serde_yaml::from_str(&pnr_problem_str).unwrap();
+  let input_str = std::fs::read_to_string(&args.input_file).unwrap();
+  let pnr_problem = match args.input_file.extension().and_then(|e| e.to_str()) {
+    Some("json") => yosys::parse(&input_str, &port_spots),
+    _ => {
+      let problem_file: resolve::ProblemFile = serde_yaml::from_str(&input_str).unwrap();
+      problem_file.resolve(&port_spots)
+    }
+  };
+  let mut pnr_problem = pnr_problem.unwrap_or_else(|e| {
+    eprintln!("Error reading {}: {}", args.input_file.display(), e);
+    std::process::exit(1);
+  });
+  // Pull-ups set in the constraint file override those of the input, on whichever pin their port ended up bound to.
+  for (port, &pull_up) in &constraints.pull_ups {
+    for io in pnr_problem.used_ios.iter_mut().filter(|io| port_spots.get(port) == Some(&io.spot)) {
+      io.config.pull_up = pull_up;
+    }
+  }
   println!("PnrProblem: {:#?}", pnr_problem);
-
-  // Load the chipdb.
-  let compressed = include_bytes!("../assets/chipdb-5k.txt.zst");
-  let data_bytes = zstd::decode_all(&compressed[..]).unwrap();
-  let data = std::str::from_utf8(&data_bytes).unwrap();
-  let db = chipdb::ChipDb::parse(&data).unwrap();
+  if let Err(errors) = pnr_problem.validate(&db) {
+    for error in &errors {
+      eprintln!("Error: {}", error);
+    }
+    eprintln!("Found {} problems in {}", errors.len(), args.input_file.display());
+    std::process::exit(1);
+  }
+  pnr_problem.fold_constants();
+  if let Err(e) = pnr_problem.promote_globals(&db) {
+    eprintln!("Error: {}", e);
+    std::process::exit(1);
+  }
 
   // Place and route the design.
   let solution = pnr::place_and_route(&db, &pnr_problem).unwrap();
   println!("PnrSolution: {:#?}", solution);
 
   // Assemble the final bitstream.
-  let compressed = include_bytes!("../assets/empty.asc.zst");
-  let data_bytes = zstd::decode_all(&compressed[..]).unwrap();
-  let empty_asc = std::str::from_utf8(&data_bytes).unwrap();
-  let mut bitstream = bitstream::parse(empty_asc).unwrap();
+  let mut bitstream = match (&args.base_asc, args.device.builtin_base_asc()) {
+    (Some(path), _) => read_text_asset(path).and_then(|asc| bitstream::parse(&asc)),
+    (None, Some(builtin)) => decode_text_asset(builtin.to_vec()).and_then(|asc| bitstream::parse(&asc)),
+    (None, None) => bitstream::empty(&db),
+  }.unwrap_or_else(|e| {
+    eprintln!("Error loading the base bitstream: {}", e);
+    std::process::exit(1);
+  });
+  if bitstream.device() != Some(&db.device) {
+    eprintln!("Error: the base bitstream is for the {:?} device, not the {}", bitstream.device(), db.device);
+    std::process::exit(1);
+  }
   bitstream::add_arcs_and_luts(&mut bitstream, &db, &pnr_problem, &solution);
-  for UsedIo { spot, is_output } in pnr_problem.used_ios {
-    bitstream::set_io_pin(&mut bitstream, spot, is_output).unwrap();
+  bitstream::add_rams(&mut bitstream, &db, &pnr_problem, &solution).unwrap();
+  bitstream::add_dsps(&mut bitstream, &db, &pnr_problem, &solution).unwrap();
+  bitstream::add_sprams(&mut bitstream, &db, &pnr_problem, &solution).unwrap();
+  bitstream::add_oscillators(&mut bitstream, &db, &pnr_problem).unwrap();
+  bitstream::add_plls(&mut bitstream, &db, &pnr_problem, &solution).unwrap();
+  bitstream::add_rgb_driver(&mut bitstream, &db, &pnr_problem).unwrap();
+  bitstream::add_hard_ips(&mut bitstream, &db, &pnr_problem, &solution).unwrap();
+  for io in &pnr_problem.used_ios {
+    bitstream::set_io_pin(&mut bitstream, &db, io).unwrap();
   }
 
   let mut s = String::new();
   bitstream::serialize(&bitstream, &mut s).unwrap();
   std::fs::write(&args.output, s).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn text_assets_may_be_zstd_compressed() {
+    let text = ".device 5k\n.io_tile 1 0\n";
+    let compressed = zstd::encode_all(text.as_bytes(), 0).unwrap();
+    assert_eq!(decode_text_asset(compressed), Ok(text.to_string()));
+    assert_eq!(decode_text_asset(text.as_bytes().to_vec()), Ok(text.to_string()));
+    // Something with the zstd magic number that isn't zstd after all.
+    assert!(decode_text_asset(vec![0x28, 0xb5, 0x2f, 0xfd, 0]).unwrap_err().starts_with("can't decompress it"));
+    assert!(decode_text_asset(vec![0xff, 0xfe]).unwrap_err().starts_with("it isn't valid UTF-8"));
+  }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use crate::{chipdb::ChipDb, pnr::IoPinSpot};
+
+/// What a pin constraint file says about the ports.
+#[derive(Debug, Default)]
+pub struct PcfConstraints {
+  pub port_spots: HashMap<String, IoPinSpot>,
+  /// Whether each port given a -pullup option has its pull-up turned on.
+  pub pull_ups: HashMap<String, bool>,
+}
+
+/// Parses a pin constraint file, made up of lines like `set_io [-nowarn] [-pullup yes|no] <port> <pin>`,
+/// resolving each package pin name to its spot on the chip.
+pub fn parse(content: &str, chipdb: &ChipDb, package: &str) -> Result<PcfConstraints, String> {
+  let mut port_spots = HashMap::new();
+  let mut pull_ups = HashMap::new();
+  let mut port_by_pin: HashMap<String, String> = HashMap::new();
+  for (line_number, line) in content.lines().enumerate() {
+    let line_number = line_number + 1;
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue;
+    }
+    let mut chunks = line.split_whitespace();
+    match chunks.next().unwrap() {
+      "set_io" => {}
+      command => return Err(format!("PCF line {}: unsupported command {:?}", line_number, command)),
+    }
+    let mut positional = Vec::new();
+    let mut pull_up = None;
+    while let Some(chunk) = chunks.next() {
+      match chunk {
+        "-nowarn" => {}
+        "-pullup" | "-pullup_resistor" => {
+          let Some(value) = chunks.next() else {
+            return Err(format!("PCF line {}: missing value for {}", line_number, chunk));
+          };
+          match (chunk, value) {
+            ("-pullup", "yes" | "1") => pull_up = Some(true),
+            ("-pullup", "no" | "0") => pull_up = Some(false),
+            // The pull-ups are always the default 100k.
+            ("-pullup_resistor", "100K") => {}
+            _ => return Err(format!("PCF line {}: unsupported value {:?} for {}", line_number, value, chunk)),
+          }
+        }
+        _ if chunk.starts_with('-') => {
+          return Err(format!("PCF line {}: unsupported option {:?}", line_number, chunk));
+        }
+        _ => positional.push(chunk),
+      }
+    }
+    let &[port, pin] = &positional[..] else {
+      return Err(format!("PCF line {}: expected set_io <port> <pin>, got {:?}", line_number, line));
+    };
+    let spot = chipdb.get_io_pin_spot(package, pin).map_err(|e| format!("PCF line {}: {}", line_number, e))?;
+    if let Some(other_port) = port_by_pin.insert(pin.to_string(), port.to_string()) {
+      return Err(format!("PCF line {}: pin {} is used by both {} and {}", line_number, pin, other_port, port));
+    }
+    if port_spots.insert(port.to_string(), spot).is_some() {
+      return Err(format!("PCF line {}: port {} is constrained more than once", line_number, port));
+    }
+    if let Some(pull_up) = pull_up {
+      pull_ups.insert(port.to_string(), pull_up);
+    }
+    println!("Constraining port {} to pin {} at {:?}", port, pin, spot);
+  }
+  Ok(PcfConstraints { port_spots, pull_ups })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chipdb::TilePos;
+
+  fn chipdb() -> ChipDb {
+    ChipDb::parse(".device 5k\n.pins sg48\n41 6 31 0\n42 6 31 1\n").unwrap()
+  }
+
+  #[test]
+  fn binds_ports_to_pins() {
+    let pcf = "# The LEDs\nset_io led 41\nset_io -nowarn -pullup yes button 42 # with a pull-up\n";
+    let constraints = parse(pcf, &chipdb(), "sg48").unwrap();
+    assert_eq!(constraints.port_spots["led"], IoPinSpot { tile: TilePos(6, 31), which: 0 });
+    assert_eq!(constraints.port_spots["button"], IoPinSpot { tile: TilePos(6, 31), which: 1 });
+    assert_eq!(constraints.pull_ups.get("button"), Some(&true));
+    assert_eq!(constraints.pull_ups.get("led"), None);
+  }
+
+  #[test]
+  fn rejects_bad_lines() {
+    let chipdb = chipdb();
+    for pcf in [
+      "set_io led 43",
+      "set_io led 41\nset_io button 41",
+      "set_io led 41\nset_io led 42",
+      "set_io -pullup maybe led 41",
+      "set_io -pullup_resistor 10K led 41",
+      "set_io -io_std SB_LVCMOS led 41",
+      "set_io led",
+      "set_frequency clk 12",
+    ] {
+      assert!(parse(pcf, &chipdb, "sg48").is_err(), "{:?}", pcf);
+    }
+    assert!(parse("set_io led 41", &chipdb, "cm81").is_err());
+  }
+}
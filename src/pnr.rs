@@ -1,16 +1,509 @@
-use std::{collections::{HashMap, HashSet, VecDeque}, hash::Hash};
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, hash::Hash};
 use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
 use serde::Deserialize;
-use crate::chipdb::{ChipDb, ChipNetIndex, ConfiguredArc, TilePos};
+use crate::chipdb::{ChipDb, ChipNetIndex, ConfiguredArc, ExtraCell, TilePos};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 #[serde(transparent)]
 pub struct LutIndex(pub usize);
 
 #[derive(Debug, Deserialize)]
+#[serde(try_from = "Lut4Spec")]
 pub struct Lut4 {
+  pub name: Option<String>,
   pub table: u16,
   pub clock_domain: Option<u32>,
+  pub clock_edge: ClockEdge,
+  pub reset_mode: ResetMode,
+  pub reset_value: ResetValue,
+}
+
+impl Lut4 {
+  pub fn combinational(name: Option<String>, table: u16) -> Self {
+    Lut4 {
+      name,
+      table,
+      clock_domain: None,
+      clock_edge: ClockEdge::default(),
+      reset_mode: ResetMode::default(),
+      reset_value: ResetValue::default(),
+    }
+  }
+}
+
+/// The clock edge a flip-flop loads on. All flip-flops in a logic tile share one clock edge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockEdge {
+  #[default]
+  Rising,
+  Falling,
+}
+
+/// Whether a flip-flop's set/reset input acts on the next clock edge, or immediately.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetMode {
+  #[default]
+  Sync,
+  Async,
+}
+
+/// The value a flip-flop takes when its set/reset input is high.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetValue {
+  #[default]
+  Clear,
+  Set,
+}
+
+/// A LUT4 as written in an input file, where the table may instead be given as a boolean expression.
+#[derive(Deserialize)]
+struct Lut4Spec {
+  #[serde(default)]
+  name: Option<String>,
+  #[serde(default)]
+  table: Option<u16>,
+  #[serde(default)]
+  expr: Option<String>,
+  clock_domain: Option<u32>,
+  #[serde(default)]
+  clock_edge: ClockEdge,
+  #[serde(default)]
+  reset_mode: ResetMode,
+  #[serde(default)]
+  reset_value: ResetValue,
+}
+
+impl TryFrom<Lut4Spec> for Lut4 {
+  type Error = String;
+
+  fn try_from(spec: Lut4Spec) -> Result<Self, String> {
+    let Lut4Spec { name, table, expr, clock_domain, clock_edge, reset_mode, reset_value } = spec;
+    let table = match (table, expr) {
+      (Some(table), None) => table,
+      (None, Some(expr)) => crate::expr::compile(&expr).map_err(|e| format!("Invalid expr {:?}: {}", expr, e))?,
+      (Some(_), Some(_)) => return Err("A LUT4 must have only one of table and expr".to_string()),
+      (None, None) => return Err("A LUT4 must have either a table or an expr".to_string()),
+    };
+    Ok(Lut4 { name, table, clock_domain, clock_edge, reset_mode, reset_value })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct RamIndex(pub usize);
+
+/// A 4 kbit block RAM (SB_RAM40_4K), with one read port and one write port.
+#[derive(Debug, Deserialize)]
+pub struct Ram {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub read_mode: RamMode,
+  #[serde(default)]
+  pub write_mode: RamMode,
+  #[serde(default)]
+  pub read_clock_edge: ClockEdge,
+  #[serde(default)]
+  pub write_clock_edge: ClockEdge,
+  /// The initial contents as 256 16-bit words, laid out like the INIT_0 to INIT_F parameters of SB_RAM40_4K,
+  /// so word k holds bits 16 * k to 16 * k + 15. Any words left out are zero.
+  #[serde(default)]
+  pub init: Vec<u16>,
+}
+
+impl Ram {
+  pub fn new(name: Option<String>) -> Self {
+    Ram {
+      name,
+      read_mode: RamMode::default(),
+      write_mode: RamMode::default(),
+      read_clock_edge: ClockEdge::default(),
+      write_clock_edge: ClockEdge::default(),
+      init: Vec::new(),
+    }
+  }
+}
+
+/// The shape of a RAM port, as words x bits. This is the READ_MODE or WRITE_MODE parameter of SB_RAM40_4K.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum RamMode {
+  #[default]
+  #[serde(rename = "256x16")]
+  Words256x16,
+  #[serde(rename = "512x8")]
+  Words512x8,
+  #[serde(rename = "1024x4")]
+  Words1024x4,
+  #[serde(rename = "2048x2")]
+  Words2048x2,
+}
+
+impl RamMode {
+  pub fn from_mode_bits(mode_bits: u64) -> Result<RamMode, String> {
+    match mode_bits {
+      0 => Ok(RamMode::Words256x16),
+      1 => Ok(RamMode::Words512x8),
+      2 => Ok(RamMode::Words1024x4),
+      3 => Ok(RamMode::Words2048x2),
+      _ => Err(format!("Invalid RAM mode: {}", mode_bits)),
+    }
+  }
+
+  pub fn mode_bits(self) -> u8 {
+    self as u8
+  }
+}
+
+/// A pin of a block RAM, named like the SB_RAM40_4K port it is part of, e.g. "RDATA_3" or "WCLK".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RamPin {
+  ReadData(u8),
+  ReadAddress(u8),
+  ReadClock,
+  ReadClockEnable,
+  ReadEnable,
+  WriteData(u8),
+  Mask(u8),
+  WriteAddress(u8),
+  WriteClock,
+  WriteClockEnable,
+  WriteEnable,
+}
+
+impl RamPin {
+  pub fn is_output(self) -> bool {
+    matches!(self, RamPin::ReadData(_))
+  }
+
+  pub fn is_clock(self) -> bool {
+    matches!(self, RamPin::ReadClock | RamPin::WriteClock)
+  }
+}
+
+impl TryFrom<String> for RamPin {
+  type Error = String;
+
+  fn try_from(name: String) -> Result<Self, String> {
+    let pin = match name.as_str() {
+      "RCLK" => RamPin::ReadClock,
+      "RCLKE" => RamPin::ReadClockEnable,
+      "RE" => RamPin::ReadEnable,
+      "WCLK" => RamPin::WriteClock,
+      "WCLKE" => RamPin::WriteClockEnable,
+      "WE" => RamPin::WriteEnable,
+      _ => {
+        let (bus, index) = name.rsplit_once('_').ok_or_else(|| format!("Unknown RAM pin: {}", name))?;
+        let index: u8 = index.parse().map_err(|_| format!("Unknown RAM pin: {}", name))?;
+        let (pin, width): (fn(u8) -> RamPin, u8) = match bus {
+          "RDATA" => (RamPin::ReadData, 16),
+          "RADDR" => (RamPin::ReadAddress, 11),
+          "WDATA" => (RamPin::WriteData, 16),
+          "MASK" => (RamPin::Mask, 16),
+          "WADDR" => (RamPin::WriteAddress, 11),
+          _ => return Err(format!("Unknown RAM pin: {}", name)),
+        };
+        if index >= width {
+          return Err(format!("RAM pin {} is out of range ({} has {} bits)", name, bus, width));
+        }
+        pin(index)
+      }
+    };
+    Ok(pin)
+  }
+}
+
+impl std::fmt::Display for RamPin {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      RamPin::ReadData(i) => write!(f, "RDATA_{}", i),
+      RamPin::ReadAddress(i) => write!(f, "RADDR_{}", i),
+      RamPin::ReadClock => write!(f, "RCLK"),
+      RamPin::ReadClockEnable => write!(f, "RCLKE"),
+      RamPin::ReadEnable => write!(f, "RE"),
+      RamPin::WriteData(i) => write!(f, "WDATA_{}", i),
+      RamPin::Mask(i) => write!(f, "MASK_{}", i),
+      RamPin::WriteAddress(i) => write!(f, "WADDR_{}", i),
+      RamPin::WriteClock => write!(f, "WCLK"),
+      RamPin::WriteClockEnable => write!(f, "WCLKE"),
+      RamPin::WriteEnable => write!(f, "WE"),
+    }
+  }
+}
+
+/// The name of a port of a hard IP cell, like "A_3" or "CLK", as listed in the chipdb's .extra_cell records.
+/// It's stored inline, so that spots can stay Copy.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PortName {
+  len: u8,
+  bytes: [u8; 23],
+}
+
+impl PortName {
+  pub fn as_str(&self) -> &str {
+    std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+  }
+}
+
+impl TryFrom<&str> for PortName {
+  type Error = String;
+
+  fn try_from(name: &str) -> Result<Self, String> {
+    let mut bytes = [0; 23];
+    if name.len() > bytes.len() {
+      return Err(format!("Port name too long: {}", name));
+    }
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(PortName { len: name.len() as u8, bytes })
+  }
+}
+
+impl TryFrom<String> for PortName {
+  type Error = String;
+
+  fn try_from(name: String) -> Result<Self, String> {
+    PortName::try_from(name.as_str())
+  }
+}
+
+impl std::fmt::Display for PortName {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl std::fmt::Debug for PortName {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{:?}", self.as_str())
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct DspIndex(pub usize);
+
+/// A 16x16 multiply-accumulate block (SB_MAC16). Its ports are named like those of SB_MAC16, with a bit index for the
+/// buses: A_0 to A_15, B_k, C_k and D_k in, O_0 to O_31 out, and single bit ports like CLK, CE, or CO.
+#[derive(Debug, Deserialize)]
+pub struct Dsp {
+  #[serde(default)]
+  pub name: Option<String>,
+  /// The parameters of SB_MAC16 (see MAC16_PARAMETERS), like A_SIGNED or TOPOUTPUT_SELECT. Any left out are 0.
+  #[serde(default)]
+  pub parameters: BTreeMap<String, u64>,
+}
+
+/// The configuration parameters of SB_MAC16, and how many bits wide each is.
+pub const MAC16_PARAMETERS: [(&str, usize); 19] = [
+  ("C_REG", 1),
+  ("A_REG", 1),
+  ("B_REG", 1),
+  ("D_REG", 1),
+  ("TOP_8x8_MULT_REG", 1),
+  ("BOT_8x8_MULT_REG", 1),
+  ("PIPELINE_16x16_MULT_REG1", 1),
+  ("PIPELINE_16x16_MULT_REG2", 1),
+  ("TOPOUTPUT_SELECT", 2),
+  ("TOPADDSUB_LOWERINPUT", 2),
+  ("TOPADDSUB_UPPERINPUT", 1),
+  ("TOPADDSUB_CARRYSELECT", 2),
+  ("BOTOUTPUT_SELECT", 2),
+  ("BOTADDSUB_LOWERINPUT", 2),
+  ("BOTADDSUB_UPPERINPUT", 1),
+  ("BOTADDSUB_CARRYSELECT", 2),
+  ("MODE_8x8", 1),
+  ("A_SIGNED", 1),
+  ("B_SIGNED", 1),
+];
+
+/// Whether a port of SB_MAC16 is one of its outputs.
+pub fn is_mac16_output(port: PortName) -> bool {
+  matches!(port.as_str(), "CO" | "ACCUMCO" | "SIGNEXTOUT") || port.as_str().starts_with("O_")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct SpramIndex(pub usize);
+
+/// A 16K x 16 single-port RAM (SB_SPRAM256KA). Its ports are named like those of SB_SPRAM256KA, with a bit index for the
+/// buses: ADDRESS_0 to ADDRESS_13, DATAIN_k, MASKWREN_0 to MASKWREN_3 and DATAOUT_k, plus WREN, CHIPSELECT, CLOCK,
+/// STANDBY, SLEEP and POWEROFF. POWEROFF is active low, so an SPRAM whose POWEROFF isn't driven is powered off.
+#[derive(Debug, Deserialize)]
+pub struct Spram {
+  #[serde(default)]
+  pub name: Option<String>,
+}
+
+/// Whether a port of SB_SPRAM256KA is one of its outputs.
+pub fn is_spram_output(port: PortName) -> bool {
+  port.as_str().starts_with("DATAOUT_")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct PllIndex(pub usize);
+
+/// Where a PLL takes its reference clock from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PllReference {
+  /// The REFERENCECLK port, routed from the fabric, like SB_PLL40_CORE.
+  #[default]
+  Core,
+  /// The PLL's own IO pad (the one PLLOUT_A comes out of), which must be declared as an input, like SB_PLL40_PAD.
+  Pad,
+}
+
+/// The dividers of a PLL with simple feedback, which makes input / (divr + 1) * (divf + 1) / 2^divq.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct PllDividers {
+  pub divr: u8,
+  pub divf: u8,
+  pub divq: u8,
+  /// The loop filter setting (1 to 6), which depends on the frequency after the divr divider.
+  pub filter_range: u8,
+}
+
+impl PllDividers {
+  /// Picks the dividers that get closest to `output_mhz` from `input_mhz`, the way icepll does.
+  pub fn for_frequencies(input_mhz: f64, output_mhz: f64) -> Result<Self, String> {
+    if !(10.0..=133.0).contains(&input_mhz) {
+      return Err(format!("PLL input frequency {} MHz is out of range (10 to 133 MHz)", input_mhz));
+    }
+    if !(16.0..=275.0).contains(&output_mhz) {
+      return Err(format!("PLL output frequency {} MHz is out of range (16 to 275 MHz)", output_mhz));
+    }
+    let mut best: Option<(f64, PllDividers)> = None;
+    for divr in 0..16 {
+      // The phase detector works from 10 to 133 MHz, and the VCO from 533 to 1066 MHz.
+      let f_pfd = input_mhz / (divr as f64 + 1.0);
+      if !(10.0..=133.0).contains(&f_pfd) {
+        continue;
+      }
+      let filter_range = if f_pfd < 17.0 {
+        1
+      } else if f_pfd < 26.0 {
+        2
+      } else if f_pfd < 44.0 {
+        3
+      } else if f_pfd < 66.0 {
+        4
+      } else if f_pfd < 101.0 {
+        5
+      } else {
+        6
+      };
+      for divf in 0..128 {
+        let f_vco = f_pfd * (divf as f64 + 1.0);
+        if !(533.0..=1066.0).contains(&f_vco) {
+          continue;
+        }
+        for divq in 1..7 {
+          let error = (f_vco / (1 << divq) as f64 - output_mhz).abs();
+          if best.is_none_or(|(best_error, _)| error < best_error) {
+            best = Some((error, PllDividers { divr, divf, divq, filter_range }));
+          }
+        }
+      }
+    }
+    best.map(|(_, dividers)| dividers).ok_or_else(|| format!("No PLL dividers make {} MHz from {} MHz", output_mhz, input_mhz))
+  }
+
+  pub fn output_mhz(&self, input_mhz: f64) -> f64 {
+    input_mhz / (self.divr as f64 + 1.0) * (self.divf as f64 + 1.0) / (1 << self.divq) as f64
+  }
+}
+
+/// A phase-locked loop with simple feedback (SB_PLL40_CORE or SB_PLL40_PAD). Its ports are named like those of the chipdb's
+/// PLL cell: PLLOUT_A, PLLOUT_B, LOCK and SDO out, and REFERENCECLK, RESETB, BYPASS, EXTFEEDBACK, DYNAMICDELAY_k,
+/// LATCHINPUTVALUE, SDI and SCLK in. RESETB is active low, so PnrProblem::fold_constants ties it high if it isn't wired.
+#[derive(Debug, Deserialize)]
+pub struct Pll {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub reference: PllReference,
+  #[serde(flatten)]
+  pub dividers: PllDividers,
+}
+
+/// Whether a port of the PLL is one of its outputs.
+pub fn is_pll_output(port: PortName) -> bool {
+  matches!(port.as_str(), "PLLOUT_A" | "PLLOUT_B" | "LOCK" | "SDO")
+}
+
+/// How much current the RGB LED driver sinks for each step of its RGBn_CURRENT settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RgbCurrentMode {
+  /// 4 mA a step, up to 24 mA.
+  #[default]
+  Full,
+  /// 2 mA a step, up to 12 mA.
+  Half,
+}
+
+/// The RGB LED driver (SB_RGBA_DRV), which sinks current through the three dedicated LED pins. Its inputs are
+/// CURREN and RGBLEDEN, which both have to be high for it to light anything, and RGB0PWM, RGB1PWM and RGB2PWM.
+#[derive(Debug, Deserialize)]
+pub struct RgbDriver {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub current_mode: RgbCurrentMode,
+  /// The current of each pin, like RGB0_CURRENT: 0 for off, then one more 1 bit for each step, from 0b000001 to 0b111111.
+  #[serde(default)]
+  pub rgb0_current: u8,
+  #[serde(default)]
+  pub rgb1_current: u8,
+  #[serde(default)]
+  pub rgb2_current: u8,
+}
+
+/// Whether a port of one of the cells that there's only one of, like the oscillators or the LED PWM IP, is an output.
+pub fn is_fixed_cell_output(port: PortName) -> bool {
+  matches!(port.as_str(), "CLKHF" | "CLKHF_FABRIC" | "CLKLF" | "CLKLF_FABRIC" | "LEDDON") || port.as_str().starts_with("PWMOUT")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct HardIpIndex(pub usize);
+
+/// Any other hard IP block that the chipdb lists as an .extra_cell, like the I2C and SPI blocks (SB_I2C and SB_SPI)
+/// or SB_WARMBOOT. Its ports are those of the chipdb's cell, like SBADRI3, SCLO or BOOT, and all it needs turned on
+/// is the block's enable bits, if it has any.
+#[derive(Debug, Deserialize)]
+pub struct HardIp {
+  #[serde(default)]
+  pub name: Option<String>,
+  /// The type of the chipdb's cell, like "I2C", "SPI" or "WARMBOOT".
+  pub cell_type: String,
+  /// Which of the chip's cells of that type to use, counting from the left, like 1 for the right I2C block.
+  /// By default it's the first one that's free.
+  #[serde(default)]
+  pub site: Option<usize>,
+  /// Other configuration bits of the cell to set, like SDA_INPUT_DELAYED of an I2C block.
+  #[serde(default)]
+  pub config: Vec<String>,
+}
+
+/// Whether a port of a hard IP block is one of its outputs.
+pub fn is_hard_ip_output(port: PortName) -> bool {
+  let port = port.as_str().trim_end_matches(|c: char| c.is_ascii_digit() || c == '_');
+  matches!(
+    port,
+    "SBDATO" | "SBACKO" | "I2CIRQ" | "I2CWKUP" | "SPIIRQ" | "SPIWKUP" | "SCLO" | "SCLOE" | "SDAO" | "SDAOE"
+      | "SO" | "SOE" | "MO" | "MOE" | "SCKO" | "SCKOE" | "MCSNO" | "MCSNOE"
+  )
+}
+
+/// The PLL cell of the chipdb that a PLL is placed at. There's no choice in where PLLs go, so they take the PLL cells in order.
+fn pll_site(chipdb: &ChipDb, pll_index: PllIndex) -> Option<usize> {
+  (0..chipdb.extra_cells.len()).filter(|&i| chipdb.extra_cells[i].cell_type == "PLL").nth(pll_index.0)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
@@ -19,63 +512,1060 @@ pub struct IoPinSpot {
   pub which: u8,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
-#[serde(tag = "type")]
-pub enum OutputSpot {
-  Pin(IoPinSpot),
-  Lut {
-    lut_index: LutIndex
-  },
-}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "type")]
+pub enum OutputSpot {
+  Pin(IoPinSpot),
+  /// The value of a DDR input pin captured on the falling clock edge (D_IN_1).
+  PinDdrIn(IoPinSpot),
+  Lut {
+    lut_index: LutIndex
+  },
+  /// A constant logic 0 or 1, written `{type: Const, value: true}`. These are removed by PnrProblem::fold_constants
+  /// before placement. It can't be Const(bool), since serde can only put the "type" tag into a variant that's a map.
+  Const {
+    value: bool,
+  },
+  /// The carry out of a LUT in a carry chain. This can only reach input 3 of the next LUT in the chain.
+  CarryOut {
+    lut_index: LutIndex,
+  },
+  /// One of the eight global networks, for nets that PnrProblem::promote_globals moved onto it.
+  /// This can drive LUT inputs (through the glb2local wires), and the clocks, clock enables and set/resets of registers.
+  Global {
+    global_net_index: u8,
+  },
+  /// A read data pin of a block RAM.
+  Ram {
+    ram_index: RamIndex,
+    pin: RamPin,
+  },
+  /// An output port of a DSP, like O_7.
+  Dsp {
+    dsp_index: DspIndex,
+    port: PortName,
+  },
+  /// A data output of a single-port RAM, like DATAOUT_5.
+  Spram {
+    spram_index: SpramIndex,
+    port: PortName,
+  },
+  /// The 48 MHz internal oscillator (SB_HFOSC), divided by 2 to the power of `divider` (CLKHF_DIV, 0 to 3).
+  /// Every use of it in a problem must have the same divider.
+  HfOsc {
+    #[serde(default)]
+    divider: u8,
+  },
+  /// The 10 kHz internal oscillator (SB_LFOSC).
+  LfOsc,
+  /// An output port of a PLL, like PLLOUT_A.
+  Pll {
+    pll_index: PllIndex,
+    port: PortName,
+  },
+  /// An output port of the LED PWM IP (SB_LEDDA_IP): PWMOUT0 to PWMOUT2, or LEDDON.
+  LedDriver {
+    port: PortName,
+  },
+  /// An output port of a hard IP block, like SBDATO0 or SCLOE.
+  HardIp {
+    hard_ip_index: HardIpIndex,
+    port: PortName,
+  },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type")]
+pub enum InputSpot {
+  Pin(IoPinSpot),
+  /// The value a DDR output pin sends after the falling clock edge (D_OUT_1).
+  PinDdrOut(IoPinSpot),
+  /// The output enable of a pin whose output_enable is signal or registered.
+  PinOutputEnable(IoPinSpot),
+  /// The clock of the input registers of an IO tile.
+  IoInputClock {
+    tile: TilePos,
+  },
+  /// The clock of the output (and output enable) registers of an IO tile.
+  IoOutputClock {
+    tile: TilePos,
+  },
+  /// The clock enable of all the registers of an IO tile.
+  IoClockEnable {
+    tile: TilePos,
+  },
+  GlobalNetIngress {
+    tile: TilePos,
+  },
+  Lut {
+    lut_index: LutIndex,
+    input_index: u8,
+  },
+  /// The clock enable of a LUT's flip-flop. All flip-flops in a logic tile share one clock enable.
+  ClockEnable {
+    lut_index: LutIndex,
+  },
+  /// The set/reset input of a LUT's flip-flop. All flip-flops in a logic tile share one set/reset net,
+  /// but each has its own reset_mode and reset_value.
+  SetReset {
+    lut_index: LutIndex,
+  },
+  /// The clock of a LUT's flip-flop, as an alternative to setting its clock_domain.
+  /// PnrProblem::promote_globals puts the driving net on a global network and sets the clock_domain.
+  Clock {
+    lut_index: LutIndex,
+  },
+  /// Any input pin of a block RAM.
+  Ram {
+    ram_index: RamIndex,
+    pin: RamPin,
+  },
+  /// An input port of a DSP, like A_3 or CLK.
+  Dsp {
+    dsp_index: DspIndex,
+    port: PortName,
+  },
+  /// An input port of a single-port RAM, like ADDRESS_3 or CLOCK.
+  Spram {
+    spram_index: SpramIndex,
+    port: PortName,
+  },
+  /// An input port of the 48 MHz oscillator: its enable CLKHFEN or power-up CLKHFPU.
+  /// PnrProblem::fold_constants ties both high if the oscillator is used and they aren't wired.
+  HfOsc {
+    port: PortName,
+  },
+  /// Likewise, CLKLFEN or CLKLFPU of the 10 kHz oscillator.
+  LfOsc {
+    port: PortName,
+  },
+  /// An input port of a PLL, like REFERENCECLK or RESETB.
+  Pll {
+    pll_index: PllIndex,
+    port: PortName,
+  },
+  /// An input port of the RGB LED driver, like RGB0PWM. The problem must have an rgb_driver to use these.
+  RgbDriver {
+    port: PortName,
+  },
+  /// An input port of the LED PWM IP, like LEDDCLK, LEDDDAT3 or LEDDADDR0.
+  LedDriver {
+    port: PortName,
+  },
+  /// An input port of a hard IP block, like SBCLKI or BOOT.
+  HardIp {
+    hard_ip_index: HardIpIndex,
+    port: PortName,
+  },
+}
+
+impl InputSpot {
+  /// Whether this is the clock input of a flip-flop or hard block, which has to be driven from a global network.
+  pub fn is_clock(&self) -> bool {
+    match self {
+      InputSpot::Clock { .. } | InputSpot::IoInputClock { .. } | InputSpot::IoOutputClock { .. } => true,
+      InputSpot::Ram { pin, .. } => pin.is_clock(),
+      InputSpot::Dsp { port, .. } => port.as_str() == "CLK",
+      InputSpot::Spram { port, .. } => port.as_str() == "CLOCK",
+      InputSpot::LedDriver { port } => port.as_str() == "LEDDCLK",
+      InputSpot::HardIp { port, .. } => port.as_str() == "SBCLKI",
+      _ => false,
+    }
+  }
+
+  pub fn can_be_driven_by_global(&self) -> bool {
+    matches!(
+      self,
+      InputSpot::Lut { .. }
+        | InputSpot::ClockEnable { .. }
+        | InputSpot::SetReset { .. }
+        | InputSpot::IoInputClock { .. }
+        | InputSpot::IoOutputClock { .. }
+        | InputSpot::IoClockEnable { .. }
+        | InputSpot::Ram { .. }
+        | InputSpot::Dsp { .. }
+        | InputSpot::Spram { .. }
+        | InputSpot::RgbDriver { .. }
+        | InputSpot::LedDriver { .. }
+        | InputSpot::HardIp { .. }
+    )
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsedIo {
+  #[serde(default)]
+  pub name: Option<String>,
+  pub spot: IoPinSpot,
+  pub direction: IoDirection,
+  #[serde(default)]
+  pub config: IoConfig,
+}
+
+/// Which ways a pin carries data. A bidirectional pin drives a net with what it reads, and is driven by another net,
+/// which it puts on the pin while its output enable is high (or all the time, which reads back what it drives).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoDirection {
+  Input,
+  Output,
+  Bidirectional,
+}
+
+impl IoDirection {
+  pub fn has_input(self) -> bool {
+    self != IoDirection::Output
+  }
+
+  pub fn has_output(self) -> bool {
+    self != IoDirection::Input
+  }
+}
+
+impl UsedIo {
+  pub fn uses_input_register(&self) -> bool {
+    self.direction.has_input() && self.config.input_mode != IoMode::Plain
+  }
+
+  pub fn uses_output_register(&self) -> bool {
+    self.direction.has_output() && (self.config.output_mode != IoMode::Plain || self.config.output_enable == OutputEnable::Registered)
+  }
+}
+
+/// How an IO's input or output passes through its IO tile: directly, through the tile's register,
+/// or double data rate, where a second data signal is captured or sent on the falling clock edge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoMode {
+  #[default]
+  Plain,
+  Registered,
+  Ddr,
+}
+
+/// Whether an output pin is always driven, or tristated by an output enable signal, which may itself be registered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEnable {
+  #[default]
+  Always,
+  Signal,
+  Registered,
+}
+
+/// The configuration of an SB_IO. The registers of both IOs in an IO tile share one input clock,
+/// one output clock and one clock enable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct IoConfig {
+  #[serde(default)]
+  pub input_mode: IoMode,
+  #[serde(default)]
+  pub output_mode: IoMode,
+  #[serde(default)]
+  pub output_enable: OutputEnable,
+  /// Whether the pin's pull-up resistor is on.
+  #[serde(default)]
+  pub pull_up: bool,
+}
+
+impl IoConfig {
+  /// The SB_IO PIN_TYPE parameter for this configuration.
+  pub fn pin_type(&self, has_output: bool) -> u8 {
+    let input_bits = match self.input_mode {
+      IoMode::Plain => 0b01,
+      IoMode::Registered | IoMode::Ddr => 0b00,
+    };
+    if !has_output {
+      return input_bits;
+    }
+    let output_bits = match self.output_mode {
+      IoMode::Plain => 0b10,
+      IoMode::Registered => 0b01,
+      IoMode::Ddr => 0b00,
+    };
+    let output_enable_bits = match self.output_enable {
+      OutputEnable::Always => 0b01,
+      OutputEnable::Signal => 0b10,
+      OutputEnable::Registered => 0b11,
+    };
+    input_bits | output_bits << 2 | output_enable_bits << 4
+  }
+
+  /// Decodes an SB_IO PIN_TYPE parameter, also returning whether the pin is an output.
+  /// A registered input can always be used as a DDR input.
+  pub fn from_pin_type(pin_type: u8, pull_up: bool) -> Result<(IoConfig, bool), String> {
+    let input_mode = match pin_type & 0b11 {
+      0b01 => IoMode::Plain,
+      0b00 => IoMode::Registered,
+      _ => return Err(format!("Unsupported PIN_TYPE {:06b}: latched inputs aren't supported", pin_type)),
+    };
+    let mut config = IoConfig { input_mode, pull_up, ..IoConfig::default() };
+    if pin_type >> 2 == 0 {
+      return Ok((config, false));
+    }
+    config.output_mode = match pin_type >> 2 & 0b11 {
+      0b10 => IoMode::Plain,
+      0b01 => IoMode::Registered,
+      0b00 => IoMode::Ddr,
+      _ => return Err(format!("Unsupported PIN_TYPE {:06b}: inverted outputs aren't supported", pin_type)),
+    };
+    config.output_enable = match pin_type >> 4 & 0b11 {
+      0b01 => OutputEnable::Always,
+      0b10 => OutputEnable::Signal,
+      0b11 => OutputEnable::Registered,
+      _ => return Err(format!("Unsupported PIN_TYPE {:06b}", pin_type)),
+    };
+    Ok((config, true))
+  }
+}
+
+/// A run of LUTs whose carry logic is connected by the dedicated carry wires, from least significant bit up.
+/// The carry out of each LUT is the majority of its input 1, input 2 and carry in, and is the next LUT's carry in.
+/// The chain's first carry in is the constant carry_in.
+#[derive(Debug, Deserialize)]
+pub struct CarryChain {
+  pub luts: Vec<LutIndex>,
+  #[serde(default)]
+  pub carry_in: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Wire {
+  pub from: OutputSpot,
+  pub to: InputSpot,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PnrProblem {
+  pub used_ios: Vec<UsedIo>,
+  pub lut4s: Vec<Lut4>,
+  pub wires: Vec<Wire>,
+  #[serde(default)]
+  pub carry_chains: Vec<CarryChain>,
+  #[serde(default)]
+  pub rams: Vec<Ram>,
+  #[serde(default)]
+  pub dsps: Vec<Dsp>,
+  #[serde(default)]
+  pub sprams: Vec<Spram>,
+  #[serde(default)]
+  pub plls: Vec<Pll>,
+  #[serde(default)]
+  pub rgb_driver: Option<RgbDriver>,
+  #[serde(default)]
+  pub hard_ips: Vec<HardIp>,
+}
+
+impl PnrProblem {
+  pub fn new() -> Self {
+    PnrProblem {
+      used_ios: Vec::new(),
+      lut4s: Vec::new(),
+      wires: Vec::new(),
+      carry_chains: Vec::new(),
+      rams: Vec::new(),
+      dsps: Vec::new(),
+      sprams: Vec::new(),
+      plls: Vec::new(),
+      rgb_driver: None,
+      hard_ips: Vec::new(),
+    }
+  }
+
+  /// Gets rid of all constant drivers. Constant LUT inputs are folded into the LUT's table,
+  /// and any other constant sinks are driven from a LUT with a constant table instead.
+  pub fn fold_constants(&mut self) {
+    // Inputs 1 and 2 of a LUT in a carry chain also feed the carry logic, so a constant 1 there has to be routed.
+    let carry_inputs: HashSet<(LutIndex, u8)> = self.carry_chains.iter()
+      .flat_map(|chain| chain.luts.iter().flat_map(|&lut_index| [(lut_index, 1), (lut_index, 2)]))
+      .collect();
+    // The oscillators only run while their enable and power-up inputs are high, and a PLL is held in reset while its RESETB is low.
+    let mut tied_high = Vec::new();
+    if self.wires.iter().any(|wire| matches!(wire.from, OutputSpot::HfOsc { .. })) {
+      tied_high.extend(["CLKHFEN", "CLKHFPU"].map(|port| InputSpot::HfOsc { port: PortName::try_from(port).unwrap() }));
+    }
+    if self.wires.iter().any(|wire| matches!(wire.from, OutputSpot::LfOsc)) {
+      tied_high.extend(["CLKLFEN", "CLKLFPU"].map(|port| InputSpot::LfOsc { port: PortName::try_from(port).unwrap() }));
+    }
+    tied_high.extend((0..self.plls.len()).map(|i| InputSpot::Pll { pll_index: PllIndex(i), port: PortName::try_from("RESETB").unwrap() }));
+    for to in tied_high {
+      if !self.wires.iter().any(|wire| wire.to == to) {
+        self.wires.push(Wire { from: OutputSpot::Const { value: true }, to });
+      }
+    }
+    let mut constant_luts = HashMap::new();
+    for wire in std::mem::take(&mut self.wires) {
+      match wire {
+        Wire { from: OutputSpot::Const { value: true }, to: InputSpot::Lut { lut_index, input_index } }
+          if carry_inputs.contains(&(lut_index, input_index)) => {
+          let lut_index = *constant_luts.entry(true).or_insert_with(|| {
+            self.lut4s.push(Lut4::combinational(Some("constant_1".to_string()), 0xffff));
+            LutIndex(self.lut4s.len() - 1)
+          });
+          self.wires.push(Wire { from: OutputSpot::Lut { lut_index }, to: wire.to });
+        }
+        Wire { from: OutputSpot::Const { value }, to: InputSpot::Lut { lut_index, input_index } } => {
+          let lut = &mut self.lut4s[lut_index.0];
+          lut.table = fold_constant_input(lut.table, input_index, value);
+          println!("Folded constant {} into input {} of {}", value as u8, input_index, self.describe_lut(lut_index));
+        }
+        Wire { from: OutputSpot::Const { value: true }, to: InputSpot::ClockEnable { .. } | InputSpot::IoClockEnable { .. } } => {
+          // Flip-flops without a clock enable are always enabled anyway.
+        }
+        Wire { from: OutputSpot::Const { value: false }, to: InputSpot::SetReset { .. } } => {
+          // Likewise, flip-flops without a set/reset are never reset.
+        }
+        Wire { from: OutputSpot::Const { value: false }, to: InputSpot::Ram { .. } | InputSpot::Dsp { .. } | InputSpot::Spram { .. } } => {
+          // RAM and DSP inputs that aren't routed are 0.
+        }
+        Wire { from: OutputSpot::Const { .. }, to } if to.is_clock() => {
+          // Left for promote_globals to reject, rather than clocking anything from a constant LUT.
+          self.wires.push(wire);
+        }
+        Wire { from: OutputSpot::Const { value }, to } => {
+          let lut_index = *constant_luts.entry(value).or_insert_with(|| {
+            self.lut4s.push(Lut4::combinational(
+              Some(format!("constant_{}", value as u8)),
+              if value { 0xffff } else { 0x0000 },
+            ));
+            LutIndex(self.lut4s.len() - 1)
+          });
+          self.wires.push(Wire { from: OutputSpot::Lut { lut_index }, to });
+        }
+        wire => self.wires.push(wire),
+      }
+    }
+  }
+
+  /// Moves every net that clocks a flip-flop onto a global network, and sets the clock_domain of the
+  /// flip-flops it clocks. Then nets driving many LUT inputs, clock enables or set/resets are moved onto any networks that are left.
+  pub fn promote_globals(&mut self, chipdb: &ChipDb) -> Result<(), String> {
+    // Global networks that the problem already uses explicitly aren't free.
+    let mut global_nets_by_driver: HashMap<OutputSpot, u8> = HashMap::new();
+    let mut used_global_nets: HashSet<u8> = self.lut4s.iter().filter_map(|lut| lut.clock_domain.map(|n| n as u8)).collect();
+    for wire in &self.wires {
+      match (wire.from, wire.to) {
+        (from, InputSpot::GlobalNetIngress { tile }) => {
+          if let Some(n) = (0..8).find(|&n| chipdb.get_global_net_ingress_tile(n) == Ok(tile)) {
+            used_global_nets.insert(n);
+            global_nets_by_driver.insert(from, n);
+          }
+        }
+        (OutputSpot::Global { global_net_index }, _) => {
+          used_global_nets.insert(global_net_index);
+        }
+        _ => {}
+      }
+    }
+    let mut free_global_nets: Vec<u8> = (0..8).rev()
+      .filter(|n| !used_global_nets.contains(n) && chipdb.get_global_net_ingress_tile(*n).is_ok())
+      .collect();
+
+    let mut clock_fan_outs: Vec<(OutputSpot, usize)> = Vec::new();
+    let mut logic_fan_outs: Vec<(OutputSpot, usize)> = Vec::new();
+    for wire in &self.wires {
+      let fan_outs = match wire.to {
+        to if to.is_clock() => &mut clock_fan_outs,
+        InputSpot::Lut { .. } | InputSpot::ClockEnable { .. } | InputSpot::SetReset { .. } | InputSpot::IoClockEnable { .. } => {
+          &mut logic_fan_outs
+        }
+        _ => continue,
+      };
+      match fan_outs.iter_mut().find(|(driver, _)| *driver == wire.from) {
+        Some((_, count)) => *count += 1,
+        None => fan_outs.push((wire.from, 1)),
+      }
+    }
+
+    if let Some((driver, _)) = clock_fan_outs.iter().find(|(driver, _)| matches!(driver, OutputSpot::Const { .. } | OutputSpot::CarryOut { .. })) {
+      return Err(format!("{:?} can't be a clock, since it can't go onto a global network", driver));
+    }
+    let mut promotions: Vec<(OutputSpot, String)> = clock_fan_outs.into_iter()
+      .filter(|(driver, _)| !global_nets_by_driver.contains_key(driver) && !matches!(driver, OutputSpot::Global { .. }))
+      .map(|(driver, count)| (driver, format!("clock of {} flip-flops", count)))
+      .collect();
+    if promotions.len() > free_global_nets.len() {
+      return Err(format!(
+        "{} nets are used as clocks, but only {} global networks are free: {:?}",
+        promotions.len(), free_global_nets.len(), promotions.iter().map(|(driver, _)| driver).collect::<Vec<_>>(),
+      ));
+    }
+    // Nets that are already on a global network serve their LUT inputs, clock enables and set/resets from it too.
+    logic_fan_outs.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    for (driver, count) in logic_fan_outs {
+      if count < GLOBAL_PROMOTION_FAN_OUT
+        || global_nets_by_driver.contains_key(&driver)
+        || promotions.iter().any(|(other, _)| *other == driver)
+        || matches!(driver, OutputSpot::Global { .. } | OutputSpot::Const { .. } | OutputSpot::CarryOut { .. })
+      {
+        continue;
+      }
+      if promotions.len() == free_global_nets.len() {
+        println!("No global network left for {:?} (fan-out of {})", driver, count);
+        continue;
+      }
+      promotions.push((driver, format!("fan-out of {}", count)));
+    }
+
+    for (driver, reason) in promotions {
+      // Prefer the network that the driving pin is dedicated to, if it has one.
+      let dedicated = match driver {
+        OutputSpot::Pin(spot) => chipdb.global_net_pins.get(&spot).and_then(|n| free_global_nets.iter().position(|m| m == n)),
+        // The PLL's outputs come out of IO pads, so the same goes for them.
+        OutputSpot::Pll { pll_index, port } => pll_site(chipdb, pll_index)
+          .and_then(|site| chipdb.extra_cells[site].port_pad(port.as_str()))
+          .and_then(|spot| chipdb.global_net_pins.get(&spot))
+          .and_then(|n| free_global_nets.iter().position(|m| m == n)),
+        _ => None,
+      };
+      let global_net_index = free_global_nets.remove(dedicated.unwrap_or(0));
+      let tile = chipdb.get_global_net_ingress_tile(global_net_index)?;
+      println!("Promoted {:?} to global network {} ({})", driver, global_net_index, reason);
+      self.wires.push(Wire { from: driver, to: InputSpot::GlobalNetIngress { tile } });
+      global_nets_by_driver.insert(driver, global_net_index);
+    }
+
+    for wire in std::mem::take(&mut self.wires) {
+      match wire {
+        Wire { from: OutputSpot::Global { global_net_index }, to: InputSpot::Clock { lut_index } } => {
+          self.lut4s[lut_index.0].clock_domain = Some(global_net_index as u32);
+        }
+        Wire { from, to: InputSpot::Clock { lut_index } } => {
+          self.lut4s[lut_index.0].clock_domain = Some(global_nets_by_driver[&from] as u32);
+        }
+        Wire { from, to } if global_nets_by_driver.contains_key(&from) && to.can_be_driven_by_global() => {
+          self.wires.push(Wire { from: OutputSpot::Global { global_net_index: global_nets_by_driver[&from] }, to });
+        }
+        wire => self.wires.push(wire),
+      }
+    }
+    Ok(())
+  }
+
+  fn describe_lut(&self, lut_index: LutIndex) -> String {
+    match self.lut4s.get(lut_index.0).and_then(|lut| lut.name.as_ref()) {
+      Some(name) => format!("LUT {} ({})", lut_index.0, name),
+      None => format!("LUT {}", lut_index.0),
+    }
+  }
+
+  fn describe_ram(&self, ram_index: RamIndex) -> String {
+    match self.rams.get(ram_index.0).and_then(|ram| ram.name.as_ref()) {
+      Some(name) => format!("RAM {} ({})", ram_index.0, name),
+      None => format!("RAM {}", ram_index.0),
+    }
+  }
+
+  fn describe_dsp(&self, dsp_index: DspIndex) -> String {
+    match self.dsps.get(dsp_index.0).and_then(|dsp| dsp.name.as_ref()) {
+      Some(name) => format!("DSP {} ({})", dsp_index.0, name),
+      None => format!("DSP {}", dsp_index.0),
+    }
+  }
+
+  fn describe_spram(&self, spram_index: SpramIndex) -> String {
+    match self.sprams.get(spram_index.0).and_then(|spram| spram.name.as_ref()) {
+      Some(name) => format!("SPRAM {} ({})", spram_index.0, name),
+      None => format!("SPRAM {}", spram_index.0),
+    }
+  }
+
+  fn describe_hard_ip(&self, hard_ip_index: HardIpIndex) -> String {
+    match self.hard_ips.get(hard_ip_index.0) {
+      Some(HardIp { name: Some(name), cell_type, .. }) => format!("{} {} ({})", cell_type, hard_ip_index.0, name),
+      Some(HardIp { name: None, cell_type, .. }) => format!("{} {}", cell_type, hard_ip_index.0),
+      None => format!("hard IP {}", hard_ip_index.0),
+    }
+  }
+
+  /// Picks the chipdb cell each hard IP block goes in, as indices into ChipDb::extra_cells.
+  pub fn hard_ip_sites(&self, chipdb: &ChipDb) -> Result<Vec<usize>, String> {
+    let mut sites: Vec<usize> = Vec::new();
+    // Blocks that ask for a particular site get it first.
+    let mut by_site: Vec<_> = self.hard_ips.iter().enumerate().collect();
+    by_site.sort_by_key(|(_, hard_ip)| hard_ip.site.is_none());
+    let mut placements = vec![0; self.hard_ips.len()];
+    for (i, hard_ip) in by_site {
+      let mut candidates: Vec<usize> = (0..chipdb.extra_cells.len()).filter(|&j| chipdb.extra_cells[j].cell_type == hard_ip.cell_type).collect();
+      candidates.sort_by_key(|&j| (chipdb.extra_cells[j].tile.0, chipdb.extra_cells[j].tile.1));
+      let description = self.describe_hard_ip(HardIpIndex(i));
+      let site = match hard_ip.site {
+        Some(site) => *candidates.get(site).ok_or_else(|| {
+          format!("{}: site {} is out of range (there are {} {} cells)", description, site, candidates.len(), hard_ip.cell_type)
+        })?,
+        None => *candidates.iter().find(|j| !sites.contains(j)).ok_or_else(|| {
+          format!("{}: no {} cell left (there are {})", description, hard_ip.cell_type, candidates.len())
+        })?,
+      };
+      if sites.contains(&site) {
+        return Err(format!("{}: {} is already taken", description, chipdb.extra_cells[site].describe()));
+      }
+      sites.push(site);
+      placements[i] = site;
+    }
+    Ok(placements)
+  }
+
+  fn describe_pll(&self, pll_index: PllIndex) -> String {
+    match self.plls.get(pll_index.0).and_then(|pll| pll.name.as_ref()) {
+      Some(name) => format!("PLL {} ({})", pll_index.0, name),
+      None => format!("PLL {}", pll_index.0),
+    }
+  }
+
+  /// Checks that the problem makes sense before we try to place it, returning every problem found.
+  pub fn validate(&self, chipdb: &ChipDb) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+
+    let mut ios = HashMap::new();
+    for (i, io) in self.used_ios.iter().enumerate() {
+      let description = match &io.name {
+        Some(name) => format!("IO {} ({})", i, name),
+        None => format!("IO {}", i),
+      };
+      if let Err(e) = chipdb.io_tile_out(io.spot) {
+        errors.push(format!("{}: {:?} is not an IO pin: {}", description, io.spot, e));
+      }
+      if ios.insert(io.spot, io).is_some() {
+        errors.push(format!("{}: {:?} is declared more than once", description, io.spot));
+      }
+    }
+
+    for (i, lut) in self.lut4s.iter().enumerate() {
+      if let Some(clock_domain) = lut.clock_domain && clock_domain >= 8 {
+        errors.push(format!("{}: clock_domain {} is out of range (0 to 7)", self.describe_lut(LutIndex(i)), clock_domain));
+      }
+    }
+
+    if self.rams.len() > chipdb.ram_tiles.len() {
+      errors.push(format!("Too many RAMs: {} > {}", self.rams.len(), chipdb.ram_tiles.len()));
+    }
+    for (i, ram) in self.rams.iter().enumerate() {
+      if ram.init.len() > 256 {
+        errors.push(format!("{}: init has {} words, but a RAM only holds 256", self.describe_ram(RamIndex(i)), ram.init.len()));
+      }
+    }
+
+    let dsp_sites: Vec<_> = chipdb.extra_cells.iter().filter(|cell| cell.cell_type == "MAC16").collect();
+    if self.dsps.len() > dsp_sites.len() {
+      errors.push(format!("Too many DSPs: {} > {}", self.dsps.len(), dsp_sites.len()));
+    }
+    for (i, dsp) in self.dsps.iter().enumerate() {
+      for (parameter, &value) in &dsp.parameters {
+        match MAC16_PARAMETERS.iter().find(|(name, _)| name == parameter) {
+          None => errors.push(format!("{}: unknown parameter {}", self.describe_dsp(DspIndex(i)), parameter)),
+          Some(&(_, width)) if value >> width != 0 => {
+            errors.push(format!("{}: {} is {} bits wide, so {} is out of range", self.describe_dsp(DspIndex(i)), parameter, width, value));
+          }
+          Some(_) => {}
+        }
+      }
+    }
+    let check_dsp_port = |errors: &mut Vec<String>, description: &str, dsp_index: DspIndex, port: PortName, is_output: bool| {
+      if dsp_index.0 >= self.dsps.len() {
+        errors.push(format!("{}: dsp_index {} is out of range (there are {} DSPs)", description, dsp_index.0, self.dsps.len()));
+      }
+      if dsp_sites.first().is_some_and(|site| !site.has_port(port.as_str())) {
+        errors.push(format!("{}: a DSP has no port {}", description, port));
+      } else if is_mac16_output(port) != is_output {
+        errors.push(format!("{}: DSP port {} is an {}", description, port, if is_output { "input" } else { "output" }));
+      }
+    };
+
+    let spram_sites: Vec<_> = chipdb.extra_cells.iter().filter(|cell| cell.cell_type == "SPRAM").collect();
+    if self.sprams.len() > spram_sites.len() {
+      errors.push(format!("Too many SPRAMs: {} > {}", self.sprams.len(), spram_sites.len()));
+    }
+    let check_spram_port = |errors: &mut Vec<String>, description: &str, spram_index: SpramIndex, port: PortName, is_output: bool| {
+      if spram_index.0 >= self.sprams.len() {
+        errors.push(format!("{}: spram_index {} is out of range (there are {} SPRAMs)", description, spram_index.0, self.sprams.len()));
+      }
+      if spram_sites.first().is_some_and(|site| !site.has_port(port.as_str())) {
+        errors.push(format!("{}: an SPRAM has no port {}", description, port));
+      } else if is_spram_output(port) != is_output {
+        errors.push(format!("{}: SPRAM port {} is an {}", description, port, if is_output { "input" } else { "output" }));
+      }
+    };
+
+    let pll_sites: Vec<_> = chipdb.extra_cells.iter().filter(|cell| cell.cell_type == "PLL").collect();
+    if self.plls.len() > pll_sites.len() {
+      errors.push(format!("Too many PLLs: {} > {}", self.plls.len(), pll_sites.len()));
+    }
+    // The IO pads that the PLL outputs come out of can't be used as inputs.
+    let mut pll_pads = HashMap::new();
+    for (i, (pll, site)) in self.plls.iter().zip(&pll_sites).enumerate() {
+      let description = self.describe_pll(PllIndex(i));
+      let PllDividers { divr, divf, divq, filter_range } = pll.dividers;
+      if divr >= 16 || divf >= 128 || !(1..=6).contains(&divq) || !(1..=6).contains(&filter_range) {
+        errors.push(format!("{}: dividers out of range (divr 0 to 15, divf 0 to 127, divq 1 to 6, filter_range 1 to 6)", description));
+      }
+      for port in ["PLLOUT_A", "PLLOUT_B"] {
+        if let Some(spot) = site.port_pad(port) {
+          pll_pads.insert(spot, PllIndex(i));
+        }
+      }
+      match pll.reference {
+        PllReference::Core => {
+          let reference_clock = InputSpot::Pll { pll_index: PllIndex(i), port: PortName::try_from("REFERENCECLK").unwrap() };
+          if !self.wires.iter().any(|wire| wire.to == reference_clock) {
+            errors.push(format!("{}: REFERENCECLK isn't wired, so it needs reference: pad", description));
+          }
+        }
+        PllReference::Pad => match site.port_pad("PLLOUT_A") {
+          None => errors.push(format!("{}: {} has no pad to take the reference clock from", description, site.describe())),
+          Some(spot) if !ios.get(&spot).is_some_and(|io| io.direction.has_input()) => {
+            errors.push(format!("{}: the reference clock comes from pin {:?}, so it must be declared as an input", description, spot));
+          }
+          Some(_) => {}
+        },
+      }
+    }
+    let check_pll_port = |errors: &mut Vec<String>, description: &str, pll_index: PllIndex, port: PortName, is_output: bool| {
+      if pll_index.0 >= self.plls.len() {
+        errors.push(format!("{}: pll_index {} is out of range (there are {} PLLs)", description, pll_index.0, self.plls.len()));
+      }
+      if pll_sites.first().is_some_and(|site| !site.has_port(port.as_str())) {
+        errors.push(format!("{}: a PLL has no port {}", description, port));
+      } else if is_pll_output(port) != is_output {
+        errors.push(format!("{}: PLL port {} is an {}", description, port, if is_output { "input" } else { "output" }));
+      }
+    };
+
+    let mut chain_positions = HashMap::new();
+    for (i, chain) in self.carry_chains.iter().enumerate() {
+      if chain.luts.is_empty() {
+        errors.push(format!("Carry chain {}: has no LUTs", i));
+      }
+      for (position, &lut_index) in chain.luts.iter().enumerate() {
+        if lut_index.0 >= self.lut4s.len() {
+          errors.push(format!("Carry chain {}: lut_index {} is out of range (there are {} LUTs)", i, lut_index.0, self.lut4s.len()));
+        } else if chain_positions.insert(lut_index, (i, position)).is_some() {
+          errors.push(format!("Carry chain {}: {} is in more than one carry chain", i, self.describe_lut(lut_index)));
+        }
+      }
+    }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
-#[serde(tag = "type")]
-pub enum InputSpot {
-  Pin(IoPinSpot),
-  GlobalNetIngress {
-    tile: TilePos,
-  },
-  Lut {
-    lut_index: LutIndex,
-    input_index: u8,
-  },
-}
+    let clocked_luts: HashSet<LutIndex> = self.wires.iter().filter_map(|wire| match wire.to {
+      InputSpot::Clock { lut_index } => Some(lut_index),
+      _ => None,
+    }).collect();
 
-#[derive(Debug, Deserialize)]
-pub struct UsedIo {
-  pub spot: IoPinSpot,
-  pub is_output: bool,
-}
+    let check_fixed_cell_port = |errors: &mut Vec<String>, description: &str, cell_type: &str, port: PortName, is_output: bool| {
+      match chipdb.find_extra_cell(cell_type) {
+        Err(e) => errors.push(format!("{}: {}", description, e)),
+        Ok(cell) if !cell.has_port(port.as_str()) => errors.push(format!("{}: the {} has no port {}", description, cell_type, port)),
+        Ok(_) if is_fixed_cell_output(port) != is_output => {
+          errors.push(format!("{}: {} port {} is an {}", description, cell_type, port, if is_output { "input" } else { "output" }));
+        }
+        Ok(_) => {}
+      }
+    };
 
-#[derive(Debug, Deserialize)]
-pub struct Wire {
-  pub from: OutputSpot,
-  pub to: InputSpot,
-}
+    if let Some(rgb_driver) = &self.rgb_driver {
+      if let Err(e) = chipdb.find_extra_cell("RGBA_DRV") {
+        errors.push(format!("RGB driver: {}", e));
+      }
+      for (i, current) in [rgb_driver.rgb0_current, rgb_driver.rgb1_current, rgb_driver.rgb2_current].into_iter().enumerate() {
+        if current >= 64 || current & (current + 1) != 0 {
+          errors.push(format!("RGB driver: rgb{}_current must be 0, or 1 to 6 bits set from the bottom, like 0b000111, got {:#b}", i, current));
+        }
+      }
+    }
 
-#[derive(Debug, Deserialize)]
-pub struct PnrProblem {
-  pub used_ios: Vec<UsedIo>,
-  pub lut4s: Vec<Lut4>,
-  pub wires: Vec<Wire>,
-}
+    let hard_ip_sites = self.hard_ip_sites(chipdb).unwrap_or_else(|e| {
+      errors.push(e);
+      Vec::new()
+    });
+    let check_hard_ip_port = |errors: &mut Vec<String>, description: &str, hard_ip_index: HardIpIndex, port: PortName, is_output: bool| {
+      let Some(&site) = hard_ip_sites.get(hard_ip_index.0) else {
+        if hard_ip_index.0 >= self.hard_ips.len() {
+          errors.push(format!("{}: hard_ip_index {} is out of range (there are {} hard IP blocks)", description, hard_ip_index.0, self.hard_ips.len()));
+        }
+        return;
+      };
+      if !chipdb.extra_cells[site].has_port(port.as_str()) {
+        errors.push(format!("{}: {} has no port {}", description, self.describe_hard_ip(hard_ip_index), port));
+      } else if is_hard_ip_output(port) != is_output {
+        errors.push(format!("{}: {} port {} is an {}", description, self.hard_ips[hard_ip_index.0].cell_type, port, if is_output { "input" } else { "output" }));
+      }
+    };
 
-impl PnrProblem {
-  pub fn new() -> Self {
-    PnrProblem {
-      used_ios: Vec::new(),
-      lut4s: Vec::new(),
-      wires: Vec::new(),
+    for (i, (hard_ip, &site)) in self.hard_ips.iter().zip(&hard_ip_sites).enumerate() {
+      let extra_cell = &chipdb.extra_cells[site];
+      for name in &hard_ip.config {
+        let is_config_bit = extra_cell.entries.get(name).is_some_and(|(tile, bit)| {
+          ExtraCell::is_config_bit(bit) || chipdb.get_tile_type(*tile).is_ok_and(|tile_type| chipdb.find_tile_bit(tile_type, bit).is_some())
+        });
+        if !is_config_bit {
+          errors.push(format!("{}: {} has no configuration bit {}", self.describe_hard_ip(HardIpIndex(i)), extra_cell.describe(), name));
+        }
+      }
+    }
+
+    let mut hfosc_divider = None;
+    let mut driven_inputs = HashMap::new();
+    for (i, Wire { from, to }) in self.wires.iter().enumerate() {
+      let description = format!("Wire {} (from {:?} to {:?})", i, from, to);
+      match *from {
+        OutputSpot::Pin(spot) => match ios.get(&spot) {
+          None => errors.push(format!("{}: pin {:?} is not declared in used_ios", description, spot)),
+          Some(io) if !io.direction.has_input() => errors.push(format!("{}: pin {:?} is declared as an output, so it can't drive anything", description, spot)),
+          Some(_) if let Some(&pll_index) = pll_pads.get(&spot) => {
+            errors.push(format!("{}: the input of pin {:?} is taken by {}", description, spot, self.describe_pll(pll_index)));
+          }
+          Some(_) => {}
+        },
+        OutputSpot::PinDdrIn(spot) => match ios.get(&spot) {
+          None => errors.push(format!("{}: pin {:?} is not declared in used_ios", description, spot)),
+          Some(io) if !io.direction.has_input() || io.config.input_mode == IoMode::Plain => {
+            errors.push(format!("{}: pin {:?} isn't a registered or DDR input", description, spot));
+          }
+          Some(_) => {}
+        },
+        OutputSpot::Lut { lut_index } => {
+          if lut_index.0 >= self.lut4s.len() {
+            errors.push(format!("{}: lut_index {} is out of range (there are {} LUTs)", description, lut_index.0, self.lut4s.len()));
+          }
+        }
+        OutputSpot::Const { .. } => {}
+        OutputSpot::CarryOut { lut_index } => {
+          let next_lut = chain_positions.get(&lut_index)
+            .and_then(|&(chain, position)| self.carry_chains[chain].luts.get(position + 1));
+          match next_lut {
+            None => errors.push(format!(
+              "{}: {} isn't followed by another LUT in a carry chain, so its carry out can't be used",
+              description, self.describe_lut(lut_index),
+            )),
+            Some(&next_lut) if !matches!(*to, InputSpot::Lut { lut_index, input_index: 3 } if lut_index == next_lut) => {
+              errors.push(format!("{}: a carry out can only drive input 3 of the next LUT in its chain, {}", description, self.describe_lut(next_lut)));
+            }
+            Some(_) => {}
+          }
+        }
+        OutputSpot::Ram { ram_index, pin } => {
+          if ram_index.0 >= self.rams.len() {
+            errors.push(format!("{}: ram_index {} is out of range (there are {} RAMs)", description, ram_index.0, self.rams.len()));
+          }
+          if !pin.is_output() {
+            errors.push(format!("{}: RAM pin {} is an input", description, pin));
+          }
+        }
+        OutputSpot::Dsp { dsp_index, port } => check_dsp_port(&mut errors, &description, dsp_index, port, true),
+        OutputSpot::Spram { spram_index, port } => check_spram_port(&mut errors, &description, spram_index, port, true),
+        OutputSpot::HfOsc { divider } => {
+          if let Err(e) = chipdb.find_extra_cell("HFOSC") {
+            errors.push(format!("{}: {}", description, e));
+          }
+          if divider >= 4 {
+            errors.push(format!("{}: divider {} is out of range (0 to 3)", description, divider));
+          }
+          match hfosc_divider {
+            None => hfosc_divider = Some(divider),
+            Some(other) if other != divider => errors.push(format!("{}: the HFOSC is already used with divider {}", description, other)),
+            Some(_) => {}
+          }
+        }
+        OutputSpot::LfOsc => {
+          if let Err(e) = chipdb.find_extra_cell("LFOSC") {
+            errors.push(format!("{}: {}", description, e));
+          }
+        }
+        OutputSpot::Pll { pll_index, port } => check_pll_port(&mut errors, &description, pll_index, port, true),
+        OutputSpot::LedDriver { port } => check_fixed_cell_port(&mut errors, &description, "LEDDA_IP", port, true),
+        OutputSpot::HardIp { hard_ip_index, port } => check_hard_ip_port(&mut errors, &description, hard_ip_index, port, true),
+        OutputSpot::Global { global_net_index } => {
+          if global_net_index >= 8 {
+            errors.push(format!("{}: global_net_index {} is out of range (0 to 7)", description, global_net_index));
+          }
+          if !to.can_be_driven_by_global() {
+            errors.push(format!("{}: a global network can only drive LUT inputs, and the clocks, clock enables and set/resets of registers", description));
+          }
+        }
+      }
+      let key = match *to {
+        InputSpot::Pin(spot) | InputSpot::PinDdrOut(spot) | InputSpot::PinOutputEnable(spot) => {
+          match (ios.get(&spot), to) {
+            (None, _) => errors.push(format!("{}: pin {:?} is not declared in used_ios", description, spot)),
+            (Some(io), _) if !io.direction.has_output() => errors.push(format!("{}: pin {:?} is declared as an input, so it can't be driven", description, spot)),
+            (Some(io), InputSpot::PinDdrOut(_)) if io.config.output_mode != IoMode::Ddr => {
+              errors.push(format!("{}: pin {:?} isn't a DDR output", description, spot));
+            }
+            (Some(io), InputSpot::PinOutputEnable(_)) if io.config.output_enable == OutputEnable::Always => {
+              errors.push(format!("{}: pin {:?} has output_enable: always", description, spot));
+            }
+            _ => {}
+          }
+          match to {
+            InputSpot::PinDdrOut(_) => format!("falling edge output of pin {:?}", spot),
+            InputSpot::PinOutputEnable(_) => format!("output enable of pin {:?}", spot),
+            _ => format!("pin {:?}", spot),
+          }
+        }
+        InputSpot::IoInputClock { tile } | InputSpot::IoOutputClock { tile } | InputSpot::IoClockEnable { tile } => {
+          let (what, uses_registers): (_, fn(&UsedIo) -> bool) = match to {
+            InputSpot::IoInputClock { .. } => ("input clock", UsedIo::uses_input_register),
+            InputSpot::IoOutputClock { .. } => ("output clock", UsedIo::uses_output_register),
+            _ => ("clock enable", |io| io.uses_input_register() || io.uses_output_register()),
+          };
+          if !self.used_ios.iter().any(|io| io.spot.tile == tile && uses_registers(io)) {
+            errors.push(format!("{}: no IO in tile {:?} uses the registers this clocks", description, tile));
+          }
+          format!("IO {} of tile {:?}", what, tile)
+        }
+        InputSpot::GlobalNetIngress { tile } => {
+          if !(0..8).any(|n| chipdb.get_global_net_ingress_tile(n) == Ok(tile)) {
+            errors.push(format!("{}: {:?} is not the ingress tile of any global network", description, tile));
+          }
+          format!("global network ingress at {:?}", tile)
+        }
+        InputSpot::Lut { lut_index, input_index } => {
+          if lut_index.0 >= self.lut4s.len() {
+            errors.push(format!("{}: lut_index {} is out of range (there are {} LUTs)", description, lut_index.0, self.lut4s.len()));
+          }
+          if input_index >= 4 {
+            errors.push(format!("{}: input_index {} is out of range (0 to 3)", description, input_index));
+          }
+          format!("input {} of {}", input_index, self.describe_lut(lut_index))
+        }
+        InputSpot::ClockEnable { lut_index } | InputSpot::SetReset { lut_index } => {
+          match self.lut4s.get(lut_index.0) {
+            None => errors.push(format!("{}: lut_index {} is out of range (there are {} LUTs)", description, lut_index.0, self.lut4s.len())),
+            Some(lut) if lut.clock_domain.is_none() && !clocked_luts.contains(&lut_index) => {
+              errors.push(format!("{}: {} has no flip-flop", description, self.describe_lut(lut_index)));
+            }
+            Some(_) => {}
+          }
+          match to {
+            InputSpot::ClockEnable { .. } => format!("clock enable of {}", self.describe_lut(lut_index)),
+            _ => format!("set/reset of {}", self.describe_lut(lut_index)),
+          }
+        }
+        InputSpot::Clock { lut_index } => {
+          match self.lut4s.get(lut_index.0) {
+            None => errors.push(format!("{}: lut_index {} is out of range (there are {} LUTs)", description, lut_index.0, self.lut4s.len())),
+            Some(lut) if lut.clock_domain.is_some() => {
+              errors.push(format!("{}: {} already has a clock_domain", description, self.describe_lut(lut_index)));
+            }
+            Some(_) => {}
+          }
+          format!("clock of {}", self.describe_lut(lut_index))
+        }
+        InputSpot::Ram { ram_index, pin } => {
+          if ram_index.0 >= self.rams.len() {
+            errors.push(format!("{}: ram_index {} is out of range (there are {} RAMs)", description, ram_index.0, self.rams.len()));
+          }
+          if pin.is_output() {
+            errors.push(format!("{}: RAM pin {} is an output", description, pin));
+          }
+          format!("{} of {}", pin, self.describe_ram(ram_index))
+        }
+        InputSpot::Dsp { dsp_index, port } => {
+          check_dsp_port(&mut errors, &description, dsp_index, port, false);
+          format!("{} of {}", port, self.describe_dsp(dsp_index))
+        }
+        InputSpot::Spram { spram_index, port } => {
+          check_spram_port(&mut errors, &description, spram_index, port, false);
+          format!("{} of {}", port, self.describe_spram(spram_index))
+        }
+        InputSpot::HfOsc { port } => {
+          check_fixed_cell_port(&mut errors, &description, "HFOSC", port, false);
+          format!("{} of the HFOSC", port)
+        }
+        InputSpot::LfOsc { port } => {
+          check_fixed_cell_port(&mut errors, &description, "LFOSC", port, false);
+          format!("{} of the LFOSC", port)
+        }
+        InputSpot::Pll { pll_index, port } => {
+          check_pll_port(&mut errors, &description, pll_index, port, false);
+          format!("{} of {}", port, self.describe_pll(pll_index))
+        }
+        InputSpot::RgbDriver { port } => {
+          if self.rgb_driver.is_none() {
+            errors.push(format!("{}: the RGB driver has to be configured with rgb_driver to use it", description));
+          }
+          check_fixed_cell_port(&mut errors, &description, "RGBA_DRV", port, false);
+          format!("{} of the RGB driver", port)
+        }
+        InputSpot::LedDriver { port } => {
+          check_fixed_cell_port(&mut errors, &description, "LEDDA_IP", port, false);
+          format!("{} of the LED PWM IP", port)
+        }
+        InputSpot::HardIp { hard_ip_index, port } => {
+          check_hard_ip_port(&mut errors, &description, hard_ip_index, port, false);
+          format!("{} of {}", port, self.describe_hard_ip(hard_ip_index))
+        }
+      };
+      if let Some(other) = driven_inputs.insert(key.clone(), i) {
+        errors.push(format!("{}: {} is already driven by wire {}", description, key, other));
+      }
+    }
+
+    match errors.is_empty() {
+      true => Ok(()),
+      false => Err(errors),
     }
   }
 }
 
+/// Nets with at least this many LUT input, clock enable and set/reset sinks get moved onto a global network, if one is free.
+const GLOBAL_PROMOTION_FAN_OUT: usize = 32;
+
+/// Returns the table of an equivalent LUT whose input `input_index` is tied to `value`.
+pub fn fold_constant_input(table: u16, input_index: u8, value: bool) -> u16 {
+  let mut folded = 0;
+  for i in 0..16 {
+    let source = match value {
+      false => i & !(1 << input_index),
+      true => i | (1 << input_index),
+    };
+    folded |= ((table >> source) & 1) << i;
+  }
+  folded
+}
+
+/// The flip-flop settings that are shared by every flip-flop in a logic tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ControlSet {
+  clock_domain: u32,
+  clock_edge: ClockEdge,
+  enable: Option<OutputSpot>,
+  set_reset: Option<OutputSpot>,
+}
+
 #[derive(Debug)]
 pub struct PnrSolution {
   pub lut_placements: Vec<(TilePos, u8)>,
+  /// The bottom tile of the pair of RAM tiles each RAM is placed in.
+  pub ram_placements: Vec<TilePos>,
+  /// The index into ChipDb::extra_cells of the MAC16 each DSP is placed at.
+  pub dsp_placements: Vec<usize>,
+  /// The index into ChipDb::extra_cells of the SPRAM site each SPRAM is placed at.
+  pub spram_placements: Vec<usize>,
+  /// The index into ChipDb::extra_cells of the PLL cell each PLL is placed at.
+  pub pll_placements: Vec<usize>,
+  /// The index into ChipDb::extra_cells of the cell each hard IP block is placed at.
+  pub hard_ip_placements: Vec<usize>,
   pub configured_arcs: Vec<ConfiguredArc>,
 }
 
+/// Picks `count` extra cells of the given type, nearest the middle first, as indices into ChipDb::extra_cells.
+/// If there aren't enough, returns how many there are.
+fn nearest_extra_cells(chipdb: &ChipDb, cell_type: &str, count: usize, middle: (f32, f32)) -> Result<Vec<usize>, usize> {
+  let mut sites: Vec<usize> = (0..chipdb.extra_cells.len()).filter(|&i| chipdb.extra_cells[i].cell_type == cell_type).collect();
+  sites.sort_by(|&a, &b| {
+    let distance = |i: usize| {
+      let tile = chipdb.extra_cells[i].tile;
+      (tile.0 as f32 + 0.5 - middle.0).abs() + (tile.1 as f32 + 0.5 - middle.1).abs()
+    };
+    distance(a).partial_cmp(&distance(b)).unwrap()
+  });
+  if count > sites.len() {
+    return Err(sites.len());
+  }
+  sites.truncate(count);
+  Ok(sites)
+}
+
 fn dijkstra<K: Copy + Eq + Hash, E: Copy>(
   start: K,
   extra_starts: &[K],
@@ -154,6 +1644,56 @@ pub fn place_and_route(
   let capacity_factor = exact_fit_capacity_factor.max(0.5);
   let epochs = 10.0 + problem.lut4s.len() as f32 / 500.0;
 
+  // RAMs go to the free RAM sites nearest the middle of the logic, and stay there while the LUTs settle around them.
+  let (mut mid_x, mut mid_y) = (0.0, 0.0);
+  for tile in &chipdb.logic_tiles {
+    mid_x += (tile.0 as f32 + 0.5) / chipdb.logic_tiles.len() as f32;
+    mid_y += (tile.1 as f32 + 0.5) / chipdb.logic_tiles.len() as f32;
+  }
+  let mut ram_sites = chipdb.ram_tiles.clone();
+  ram_sites.sort_by(|a, b| {
+    let distance = |tile: &TilePos| (tile.0 as f32 + 0.5 - mid_x).abs() + (tile.1 as f32 + 1.0 - mid_y).abs();
+    distance(a).partial_cmp(&distance(b)).unwrap()
+  });
+  if problem.rams.len() > ram_sites.len() {
+    return Err(format!("Too many RAMs: {} > {}", problem.rams.len(), ram_sites.len()));
+  }
+  let ram_placements: Vec<TilePos> = ram_sites[..problem.rams.len()].to_vec();
+  let ram_position = |ram_index: &RamIndex| {
+    let tile = ram_placements[ram_index.0];
+    (tile.0 as f32 + 0.5, tile.1 as f32 + 1.0)
+  };
+  // Likewise for the DSPs and SPRAMs.
+  let dsp_placements = nearest_extra_cells(chipdb, "MAC16", problem.dsps.len(), (mid_x, mid_y))
+    .map_err(|count| format!("Too many DSPs: {} > {}", problem.dsps.len(), count))?;
+  let dsp_position = |dsp_index: &DspIndex| {
+    let tile = chipdb.extra_cells[dsp_placements[dsp_index.0]].tile;
+    (tile.0 as f32 + 0.5, tile.1 as f32 + 0.5)
+  };
+  let spram_placements = nearest_extra_cells(chipdb, "SPRAM", problem.sprams.len(), (mid_x, mid_y))
+    .map_err(|count| format!("Too many SPRAMs: {} > {}", problem.sprams.len(), count))?;
+  let spram_position = |spram_index: &SpramIndex| {
+    let tile = chipdb.extra_cells[spram_placements[spram_index.0]].tile;
+    (tile.0 as f32 + 0.5, tile.1 as f32 + 0.5)
+  };
+  let pll_placements = (0..problem.plls.len())
+    .map(|i| pll_site(chipdb, PllIndex(i)).ok_or_else(|| format!("Too many PLLs: {}", problem.plls.len())))
+    .collect::<Result<Vec<_>, _>>()?;
+  let pll_position = |pll_index: &PllIndex| {
+    let tile = chipdb.extra_cells[pll_placements[pll_index.0]].tile;
+    (tile.0 as f32 + 0.5, tile.1 as f32 + 0.5)
+  };
+  let hard_ip_placements = problem.hard_ip_sites(chipdb)?;
+  let hard_ip_position = |hard_ip_index: &HardIpIndex| {
+    let tile = chipdb.extra_cells[hard_ip_placements[hard_ip_index.0]].tile;
+    (tile.0 as f32 + 0.5, tile.1 as f32 + 0.5)
+  };
+  // The oscillators and LED drivers have a spot of their own.
+  let fixed_cell_position = |cell_type: &str| {
+    let tile = chipdb.find_extra_cell(cell_type).map_or(TilePos(0, 0), |cell| cell.tile);
+    (tile.0 as f32 + 0.5, tile.1 as f32 + 0.5)
+  };
+
   for iter in 0..(timescale * epochs) as i32 {
     let t = iter as f32 / timescale;
     let correction_factor = 1.0 - 0.8 * (-t).exp();
@@ -267,13 +1807,41 @@ pub fn place_and_route(
     // Pull on edges.
     for Wire { from: output, to: input } in &problem.wires {
       let start = match output {
-        OutputSpot::Pin(IoPinSpot { tile: pos, .. }) => (pos.0 as f32 + 0.5, pos.1 as f32 + 0.5),
+        OutputSpot::Pin(IoPinSpot { tile: pos, .. })
+        | OutputSpot::PinDdrIn(IoPinSpot { tile: pos, .. }) => (pos.0 as f32 + 0.5, pos.1 as f32 + 0.5),
         OutputSpot::Lut { lut_index } => positions[lut_index.0],
+        OutputSpot::Const { .. } | OutputSpot::Global { .. } => continue,
+        OutputSpot::CarryOut { lut_index } => positions[lut_index.0],
+        OutputSpot::Ram { ram_index, .. } => ram_position(ram_index),
+        OutputSpot::Dsp { dsp_index, .. } => dsp_position(dsp_index),
+        OutputSpot::Spram { spram_index, .. } => spram_position(spram_index),
+        OutputSpot::HfOsc { .. } => fixed_cell_position("HFOSC"),
+        OutputSpot::LfOsc => fixed_cell_position("LFOSC"),
+        OutputSpot::Pll { pll_index, .. } => pll_position(pll_index),
+        OutputSpot::LedDriver { .. } => fixed_cell_position("LEDDA_IP"),
+        OutputSpot::HardIp { hard_ip_index, .. } => hard_ip_position(hard_ip_index),
       };
       let end = match input {
         InputSpot::Pin(IoPinSpot { tile: pos, .. })
+        | InputSpot::PinDdrOut(IoPinSpot { tile: pos, .. })
+        | InputSpot::PinOutputEnable(IoPinSpot { tile: pos, .. })
+        | InputSpot::IoInputClock { tile: pos }
+        | InputSpot::IoOutputClock { tile: pos }
+        | InputSpot::IoClockEnable { tile: pos }
         | InputSpot::GlobalNetIngress { tile: pos } => (pos.0 as f32 + 0.5, pos.1 as f32 + 0.5),
-        InputSpot::Lut { lut_index, input_index: _ } => positions[lut_index.0],
+        InputSpot::Lut { lut_index, input_index: _ }
+        | InputSpot::ClockEnable { lut_index }
+        | InputSpot::SetReset { lut_index }
+        | InputSpot::Clock { lut_index } => positions[lut_index.0],
+        InputSpot::Ram { ram_index, .. } => ram_position(ram_index),
+        InputSpot::Dsp { dsp_index, .. } => dsp_position(dsp_index),
+        InputSpot::Spram { spram_index, .. } => spram_position(spram_index),
+        InputSpot::HfOsc { .. } => fixed_cell_position("HFOSC"),
+        InputSpot::LfOsc { .. } => fixed_cell_position("LFOSC"),
+        InputSpot::Pll { pll_index, .. } => pll_position(pll_index),
+        InputSpot::RgbDriver { .. } => fixed_cell_position("RGBA_DRV"),
+        InputSpot::LedDriver { .. } => fixed_cell_position("LEDDA_IP"),
+        InputSpot::HardIp { hard_ip_index, .. } => hard_ip_position(hard_ip_index),
       };
       let dx = end.0 - start.0;
       let dy = end.1 - start.1;
@@ -281,16 +1849,38 @@ pub fn place_and_route(
       if distance > 0.0 {
         let scale = tug_factor / (1.0 + distance);
         match output {
-          OutputSpot::Pin(_) => {},
-          OutputSpot::Lut { lut_index: LutIndex(index) } => {
+          OutputSpot::Pin(_)
+          | OutputSpot::PinDdrIn(_)
+          | OutputSpot::Const { .. }
+          | OutputSpot::Global { .. }
+          | OutputSpot::Ram { .. }
+          | OutputSpot::Dsp { .. }
+          | OutputSpot::Spram { .. }
+          | OutputSpot::HfOsc { .. }
+          | OutputSpot::LfOsc
+          | OutputSpot::Pll { .. }
+          | OutputSpot::LedDriver { .. }
+          | OutputSpot::HardIp { .. } => {},
+          OutputSpot::Lut { lut_index: LutIndex(index) }
+          | OutputSpot::CarryOut { lut_index: LutIndex(index) } => {
             positions[*index].0 += dx * scale;
             positions[*index].1 += dy * scale;
           }
         };
         match input {
-          InputSpot::Pin(_) => {},
-          InputSpot::GlobalNetIngress { .. } => {},
-          InputSpot::Lut { lut_index: LutIndex(index), input_index: _ } => {
+          InputSpot::Pin(_)
+          | InputSpot::PinDdrOut(_)
+          | InputSpot::PinOutputEnable(_)
+          | InputSpot::IoInputClock { .. }
+          | InputSpot::IoOutputClock { .. }
+          | InputSpot::IoClockEnable { .. } => {},
+          InputSpot::GlobalNetIngress { .. } | InputSpot::Ram { .. } | InputSpot::Dsp { .. } | InputSpot::Spram { .. } => {},
+          InputSpot::HfOsc { .. } | InputSpot::LfOsc { .. } | InputSpot::Pll { .. } => {},
+          InputSpot::RgbDriver { .. } | InputSpot::LedDriver { .. } | InputSpot::HardIp { .. } => {},
+          InputSpot::Lut { lut_index: LutIndex(index), input_index: _ }
+          | InputSpot::ClockEnable { lut_index: LutIndex(index) }
+          | InputSpot::SetReset { lut_index: LutIndex(index) }
+          | InputSpot::Clock { lut_index: LutIndex(index) } => {
             positions[*index].0 -= dx * scale;
             positions[*index].1 -= dy * scale;
           }
@@ -302,8 +1892,111 @@ pub fn place_and_route(
   // Assign LUTs to tiles, sorting by y.
   let mut luts_by_y: Vec<usize> = (0..problem.lut4s.len()).collect();
   luts_by_y.sort_by(|a, b| positions[*a].1.partial_cmp(&positions[*b].1).unwrap());
+  let mut control_sets: Vec<Option<ControlSet>> = problem.lut4s.iter()
+    .map(|lut| lut.clock_domain.map(|clock_domain| ControlSet { clock_domain, clock_edge: lut.clock_edge, enable: None, set_reset: None }))
+    .collect();
+  for wire in &problem.wires {
+    match wire.to {
+      InputSpot::ClockEnable { lut_index } => if let Some(control_set) = &mut control_sets[lut_index.0] {
+        control_set.enable = Some(wire.from);
+      },
+      InputSpot::SetReset { lut_index } => if let Some(control_set) = &mut control_sets[lut_index.0] {
+        control_set.set_reset = Some(wire.from);
+      },
+      _ => {}
+    }
+  }
   let mut consumed_count = HashMap::new();
-  let mut find_free = |x: f32, y: f32| {
+  let mut tile_control_sets: HashMap<TilePos, ControlSet> = HashMap::new();
+  let mut lut_placements = vec![(TilePos(0, 0), 0); problem.lut4s.len()];
+
+  // Place the carry chains first, as each needs a run of consecutive free tiles in one column.
+  let mut placed = vec![false; problem.lut4s.len()];
+  for (chain_index, chain) in problem.carry_chains.iter().enumerate() {
+    let tile_count = chain.luts.len().div_ceil(base_luts_per_tile);
+    let chain_tiles = |start: TilePos| (0..tile_count).map(move |i| TilePos::from_usize(start.0 as usize, start.1 as usize + i));
+    let (mut cx, mut cy) = (0.0, 0.0);
+    for lut_index in &chain.luts {
+      cx += positions[lut_index.0].0 / chain.luts.len() as f32;
+      cy += positions[lut_index.0].1 / chain.luts.len() as f32;
+    }
+    let mut best = None;
+    for &start in &chipdb.logic_tiles {
+      if start.1 as usize + tile_count > 255 {
+        continue;
+      }
+      let is_free = |tile| logic_tiles_hashset.contains(&tile) && !consumed_count.contains_key(&tile);
+      if !chain_tiles(start).all(is_free) {
+        continue;
+      }
+      let (tx, ty) = (start.0 as f32 + 0.5, start.1 as f32 + tile_count as f32 / 2.0);
+      let distance = (tx - cx).abs() + (ty - cy).abs();
+      if best.is_none_or(|(_, d)| distance < d) {
+        best = Some((start, distance));
+      }
+    }
+    let Some((start, _)) = best else {
+      return Err(format!("No run of {} free logic tiles in one column for carry chain {}", tile_count, chain_index));
+    };
+    for (tile, luts) in chain_tiles(start).zip(chain.luts.chunks(base_luts_per_tile)) {
+      for (lut_number, &lut_index) in luts.iter().enumerate() {
+        lut_placements[lut_index.0] = (tile, lut_number as u8);
+        placed[lut_index.0] = true;
+        if let Some(control_set) = control_sets[lut_index.0] {
+          match tile_control_sets.insert(tile, control_set) {
+            Some(other) if other != control_set => {
+              return Err(format!(
+                "Carry chain {}: flip-flops that must share a tile have different control sets ({} and others)",
+                chain_index, problem.describe_lut(lut_index),
+              ));
+            }
+            _ => {}
+          }
+        }
+      }
+      consumed_count.insert(tile, luts.len());
+    }
+  }
+
+  // Flip-flops go first, as they can only share a tile with flip-flops of the same control set.
+  // A flip-flop may only start a new tile if that still leaves enough empty tiles for every control set's
+  // remaining flip-flops, so a legal packing always results. Combinational LUTs then fill in the gaps.
+  let mut unplaced_by_control_set: HashMap<ControlSet, usize> = HashMap::new();
+  for i in (0..problem.lut4s.len()).filter(|&i| !placed[i]) {
+    if let Some(control_set) = control_sets[i] {
+      *unplaced_by_control_set.entry(control_set).or_default() += 1;
+    }
+  }
+  let mut free_slots_by_control_set: HashMap<ControlSet, usize> = HashMap::new();
+  for (tile, control_set) in &tile_control_sets {
+    *free_slots_by_control_set.entry(*control_set).or_default() += base_luts_per_tile - consumed_count[tile];
+  }
+  let tiles_needed = |unplaced: &HashMap<ControlSet, usize>, free_slots: &HashMap<ControlSet, usize>| -> usize {
+    unplaced.iter()
+      .map(|(control_set, &count)| count.saturating_sub(free_slots.get(control_set).copied().unwrap_or(0)).div_ceil(base_luts_per_tile))
+      .sum()
+  };
+  let mut empty_tiles = chipdb.logic_tiles.iter().filter(|tile| !consumed_count.contains_key(tile)).count();
+  let needed = tiles_needed(&unplaced_by_control_set, &free_slots_by_control_set);
+  if needed > empty_tiles {
+    return Err(format!(
+      "The flip-flops need at least {} more logic tiles, but only {} are free: all flip-flops in a tile share one clock, \
+      clock edge, enable and set/reset, and {} different combinations of these are used",
+      needed, empty_tiles, unplaced_by_control_set.len(),
+    ));
+  }
+
+  let mut find_free = |x: f32, y: f32, control_set: Option<ControlSet>| {
+    let may_start_tile = match control_set {
+      None => true,
+      Some(control_set) => {
+        let mut unplaced = unplaced_by_control_set.clone();
+        let mut free_slots = free_slots_by_control_set.clone();
+        *unplaced.get_mut(&control_set).unwrap() -= 1;
+        *free_slots.entry(control_set).or_default() += base_luts_per_tile - 1;
+        tiles_needed(&unplaced, &free_slots) < empty_tiles
+      }
+    };
     let mut best = None;
     // FIXME: Do a local search instead.
     for &tile in &chipdb.logic_tiles {
@@ -312,47 +2005,137 @@ pub fn place_and_route(
       if consumed >= base_luts_per_tile {
         continue;
       }
+      // All flip-flops in a tile must share the same control set.
+      if let Some(control_set) = control_set {
+        match tile_control_sets.get(&tile) {
+          Some(tile_control_set) if *tile_control_set != control_set => continue,
+          None if consumed > 0 || !may_start_tile => continue,
+          _ => {}
+        }
+      }
       let (tx, ty) = (tile.0 as f32 + 0.5, tile.1 as f32 + 0.5);
       let distance = (tx - x).abs() + (ty - y).abs();
       if best.map_or(true, |(_, d)| distance < d) {
         best = Some((tile, distance));
       }
     }
-    let tile = best.unwrap().0;
+    let tile = best?.0;
     let lut_number = consumed_count.entry(tile).or_insert(0);
+    if *lut_number == 0 {
+      empty_tiles -= 1;
+    }
+    if let Some(control_set) = control_set {
+      *unplaced_by_control_set.get_mut(&control_set).unwrap() -= 1;
+      if tile_control_sets.insert(tile, control_set).is_none() {
+        *free_slots_by_control_set.entry(control_set).or_default() += base_luts_per_tile;
+      }
+      *free_slots_by_control_set.get_mut(&control_set).unwrap() -= 1;
+    }
     let placement = (tile, *lut_number as u8);
     *lut_number += 1;
-    placement
+    Some(placement)
   };
-  let mut lut_placements = vec![(TilePos(0, 0), 0); problem.lut4s.len()];
-  for &i in &luts_by_y {
+  let flip_flops_first = luts_by_y.iter().filter(|&&i| control_sets[i].is_some())
+    .chain(luts_by_y.iter().filter(|&&i| control_sets[i].is_none()));
+  for &i in flip_flops_first.filter(|&&i| !placed[i]) {
     let (x, y) = positions[i];
-    let (tile, lut_number) = find_free(x, y);
+    let Some((tile, lut_number)) = find_free(x, y, control_sets[i]) else {
+      return Err(format!("No free logic tile for {}", problem.describe_lut(LutIndex(i))));
+    };
     lut_placements[i] = (tile, lut_number);
   }
 
   let mut chip_nets_by_output: HashMap<OutputSpot, Vec<ChipNetIndex>> = HashMap::new();
   let mut consumed_chip_nets: HashSet<ChipNetIndex> = HashSet::new();
   let mut configured_arcs: Vec<ConfiguredArc> = Vec::new();
+
+  // Where a carry chain continues into the tile above, the carry has to be let in through carry_in_mux.
+  for chain in &problem.carry_chains {
+    for pair in chain.luts.windows(2) {
+      let (tile, lut_number) = lut_placements[pair[1].0];
+      if lut_number != 0 {
+        continue;
+      }
+      let carry_in = chipdb.get_net_by_name(tile, "carry_in")?;
+      let carry_in_mux = chipdb.get_net_by_name(tile, "carry_in_mux")?;
+      let arc = chipdb.get_configured_arc_between(carry_in, carry_in_mux)
+        .ok_or_else(|| format!("No carry_in_mux arc in tile {:?}", tile))?;
+      configured_arcs.push(arc);
+      consumed_chip_nets.insert(carry_in_mux);
+      chip_nets_by_output.entry(OutputSpot::CarryOut { lut_index: pair[0] }).or_default().push(carry_in_mux);
+    }
+  }
+
   for (i, &Wire { from, to }) in problem.wires.iter().enumerate() {
     if i % 100 == 0 {
       println!("Routing wire {}/{}", i, problem.wires.len());
     }
     let from_net = match from {
       OutputSpot::Pin(io_pin_spot) => chipdb.io_tile_out(io_pin_spot),
+      OutputSpot::PinDdrIn(IoPinSpot { tile, which }) => chipdb.get_net_by_name(tile, &format!("io_{}/D_IN_1", which)),
       OutputSpot::Lut { lut_index } => {
         let (tile, lut_number) = lut_placements[lut_index.0];
         chipdb.ff_out(tile, lut_number)
       }
+      OutputSpot::Const { .. } => Err("Constant drivers must be folded away before routing".to_string()),
+      OutputSpot::CarryOut { lut_index } => {
+        let (tile, lut_number) = lut_placements[lut_index.0];
+        chipdb.get_net_by_name(tile, &format!("lutff_{}/cout", lut_number))
+      }
+      // The search finds its way from a global network to a LUT input through glb2local and the local tracks.
+      OutputSpot::Global { global_net_index } => chipdb.get_global_net(global_net_index),
+      OutputSpot::Ram { ram_index, pin } => chipdb.ram_net(ram_placements[ram_index.0], pin),
+      OutputSpot::Dsp { dsp_index, port } => chipdb.extra_cell_net(&chipdb.extra_cells[dsp_placements[dsp_index.0]], port.as_str()),
+      OutputSpot::Spram { spram_index, port } => {
+        chipdb.extra_cell_net(&chipdb.extra_cells[spram_placements[spram_index.0]], port.as_str())
+      }
+      OutputSpot::HfOsc { .. } => chipdb.oscillator_net("HFOSC", "CLKHF"),
+      OutputSpot::LfOsc => chipdb.oscillator_net("LFOSC", "CLKLF"),
+      OutputSpot::Pll { pll_index, port } => chipdb.extra_cell_net(&chipdb.extra_cells[pll_placements[pll_index.0]], port.as_str()),
+      OutputSpot::LedDriver { port } => chipdb.extra_cell_net(chipdb.find_extra_cell("LEDDA_IP")?, port.as_str()),
+      OutputSpot::HardIp { hard_ip_index, port } => {
+        chipdb.extra_cell_net(&chipdb.extra_cells[hard_ip_placements[hard_ip_index.0]], port.as_str())
+      }
     }?;
     let to_net = match to {
       InputSpot::Pin(io_pin_spot) => chipdb.io_tile_in(io_pin_spot),
+      InputSpot::PinDdrOut(IoPinSpot { tile, which }) => chipdb.get_net_by_name(tile, &format!("io_{}/D_OUT_1", which)),
+      InputSpot::PinOutputEnable(IoPinSpot { tile, which }) => chipdb.get_net_by_name(tile, &format!("io_{}/OUT_ENB", which)),
+      InputSpot::IoInputClock { tile } => chipdb.get_net_by_name(tile, "io_global/inclk"),
+      InputSpot::IoOutputClock { tile } => chipdb.get_net_by_name(tile, "io_global/outclk"),
+      InputSpot::IoClockEnable { tile } => chipdb.get_net_by_name(tile, "io_global/cen"),
       | InputSpot::GlobalNetIngress { tile: pos } => chipdb.get_net_by_name(pos, "fabout"),
       InputSpot::Lut { lut_index, input_index } => {
         let (tile, lut_number) = lut_placements[lut_index.0];
         chipdb.ff_in(tile, lut_number, input_index)
       }
+      InputSpot::ClockEnable { lut_index } => {
+        let (tile, _) = lut_placements[lut_index.0];
+        chipdb.get_net_by_name(tile, "lutff_global/cen")
+      }
+      InputSpot::SetReset { lut_index } => {
+        let (tile, _) = lut_placements[lut_index.0];
+        chipdb.get_net_by_name(tile, "lutff_global/s_r")
+      }
+      InputSpot::Clock { .. } => Err("Clock wires must be promoted to global networks before routing".to_string()),
+      InputSpot::Ram { ram_index, pin } => chipdb.ram_net(ram_placements[ram_index.0], pin),
+      InputSpot::Dsp { dsp_index, port } => chipdb.extra_cell_net(&chipdb.extra_cells[dsp_placements[dsp_index.0]], port.as_str()),
+      InputSpot::Spram { spram_index, port } => {
+        chipdb.extra_cell_net(&chipdb.extra_cells[spram_placements[spram_index.0]], port.as_str())
+      }
+      InputSpot::HfOsc { port } => chipdb.extra_cell_net(chipdb.find_extra_cell("HFOSC")?, port.as_str()),
+      InputSpot::LfOsc { port } => chipdb.extra_cell_net(chipdb.find_extra_cell("LFOSC")?, port.as_str()),
+      InputSpot::Pll { pll_index, port } => chipdb.extra_cell_net(&chipdb.extra_cells[pll_placements[pll_index.0]], port.as_str()),
+      InputSpot::RgbDriver { port } => chipdb.extra_cell_net(chipdb.find_extra_cell("RGBA_DRV")?, port.as_str()),
+      InputSpot::LedDriver { port } => chipdb.extra_cell_net(chipdb.find_extra_cell("LEDDA_IP")?, port.as_str()),
+      InputSpot::HardIp { hard_ip_index, port } => {
+        chipdb.extra_cell_net(&chipdb.extra_cells[hard_ip_placements[hard_ip_index.0]], port.as_str())
+      }
     }?;
+    // Several flip-flops in a tile may share an input like the clock enable, which only needs routing once.
+    if chip_nets_by_output.get(&from).is_some_and(|nets| nets.contains(&to_net)) {
+      continue;
+    }
     let extra_starts = match chip_nets_by_output.get(&from) {
       Some(nets) => &nets[..],
       None => &[],
@@ -374,6 +2157,452 @@ pub fn place_and_route(
 
   Ok(PnrSolution {
     lut_placements,
+    ram_placements,
+    dsp_placements,
+    spram_placements,
+    pll_placements,
+    hard_ip_placements,
     configured_arcs,
   })
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn validate_reports_every_error() {
+    let chipdb = ChipDb::parse(".device 5k\n.net 0\n1 0 io_0/D_IN_0\n").unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios:\n",
+      "  - {spot: {tile: [1, 0], which: 0}, direction: input}\n",
+      "  - {spot: {tile: [2, 0], which: 0}, direction: output}\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: 8}\n",
+      "wires:\n",
+      "  - {from: {type: Lut, lut_index: 0}, to: {type: Lut, lut_index: 1, input_index: 4}}\n",
+      "  - {from: {type: Pin, tile: [2, 0], which: 0}, to: {type: Lut, lut_index: 0, input_index: 0}}\n",
+      "  - {from: {type: Pin, tile: [1, 0], which: 0}, to: {type: Lut, lut_index: 0, input_index: 0}}\n",
+      "  - {from: {type: Lut, lut_index: 0}, to: {type: GlobalNetIngress, tile: [0, 0]}}\n",
+    )).unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    for expected in [
+      "IO 1: IoPinSpot { tile: TilePos(2, 0), which: 0 } is not an IO pin",
+      "LUT 0: clock_domain 8 is out of range",
+      "lut_index 1 is out of range",
+      "input_index 4 is out of range",
+      "is declared as an output, so it can't drive anything",
+      "input 0 of LUT 0 is already driven by wire 1",
+      "TilePos(0, 0) is not the ingress tile of any global network",
+    ] {
+      assert!(errors.iter().any(|e| e.contains(expected)), "{:?} not in {:#?}", expected, errors);
+    }
+    assert_eq!(errors.len(), 7, "{:#?}", errors);
+
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios:\n",
+      "  - {spot: {tile: [1, 0], which: 0}, direction: input}\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: 7}\n",
+      "wires:\n",
+      "  - {from: {type: Pin, tile: [1, 0], which: 0}, to: {type: Lut, lut_index: 0, input_index: 0}}\n",
+    )).unwrap();
+    assert_eq!(problem.validate(&chipdb), Ok(()));
+  }
+
+  #[test]
+  fn constants_fold_into_lut_tables() {
+    // Tying input 1 of an AND of inputs 0 and 1 to 1 leaves just input 0.
+    assert_eq!(fold_constant_input(0x8888, 1, true), 0xaaaa);
+    assert_eq!(fold_constant_input(0x8888, 1, false), 0x0000);
+    let mut problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios:\n",
+      "  - {spot: {tile: [1, 0], which: 0}, direction: output}\n",
+      "  - {spot: {tile: [1, 0], which: 1}, direction: output}\n",
+      "lut4s:\n",
+      "  - {table: 0x8888, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: Const, value: true}, to: {type: Lut, lut_index: 0, input_index: 1}}\n",
+      "  - {from: {type: Const, value: true}, to: {type: Pin, tile: [1, 0], which: 0}}\n",
+      "  - {from: {type: Const, value: true}, to: {type: Pin, tile: [1, 0], which: 1}}\n",
+    )).unwrap();
+    problem.fold_constants();
+    assert_eq!(problem.lut4s[0].table, 0xaaaa);
+    // Constants that can't be folded share one constant LUT.
+    assert_eq!(problem.lut4s.len(), 2);
+    assert_eq!(problem.lut4s[1].table, 0xffff);
+    assert_eq!(problem.wires.len(), 2);
+    assert!(problem.wires.iter().all(|wire| wire.from == OutputSpot::Lut { lut_index: LutIndex(1) }));
+  }
+
+  #[test]
+  fn clock_enables_and_resets() {
+    let chipdb = ChipDb::parse(".device 5k\n").unwrap();
+    let mut problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: 7}\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: Const, value: true}, to: {type: ClockEnable, lut_index: 0}}\n",
+      "  - {from: {type: Lut, lut_index: 0}, to: {type: ClockEnable, lut_index: 1}}\n",
+      "  - {from: {type: Const, value: false}, to: {type: SetReset, lut_index: 0}}\n",
+    )).unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    assert_eq!(errors.len(), 1, "{:#?}", errors);
+    assert!(errors[0].ends_with("LUT 1 has no flip-flop"), "{}", errors[0]);
+    // An enable that's always on, or a set/reset that's always off, is the same as none at all.
+    problem.fold_constants();
+    assert_eq!(problem.wires.len(), 1);
+    assert_eq!(problem.lut4s.len(), 2);
+  }
+
+  #[test]
+  fn carry_chains_take_consecutive_tiles_in_a_column() {
+    // Only column 1 has two logic tiles on top of each other.
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".logic_tile 1 1\n.logic_tile 1 2\n.logic_tile 2 1\n.logic_tile 3 3\n",
+      ".net 0\n1 2 carry_in\n",
+      ".net 1\n1 2 carry_in_mux\n",
+      ".buffer 1 2 1 B1[49]\n1 0\n",
+    )).unwrap();
+    let lut = "  - {table: 0x6996, clock_domain: null}\n";
+    let problem: PnrProblem = serde_yaml::from_str(&format!(
+      "used_ios: []\nlut4s:\n{}wires: []\ncarry_chains:\n  - {{luts: [0, 1, 2, 3, 4, 5, 6, 7, 8]}}\n",
+      lut.repeat(9),
+    )).unwrap();
+    let solution = place_and_route(&chipdb, &problem).unwrap();
+    for (i, &placement) in solution.lut_placements.iter().enumerate() {
+      assert_eq!(placement, (TilePos::from_usize(1, 1 + i / 8), (i % 8) as u8));
+    }
+    // The carry out of LUT 7 reaches the tile above through carry_in_mux.
+    assert_eq!(solution.configured_arcs, vec![chipdb.get_configured_arc_between(ChipNetIndex(0), ChipNetIndex(1)).unwrap()]);
+  }
+
+  #[test]
+  fn carry_outs_only_reach_the_next_lut_in_the_chain() {
+    let chipdb = ChipDb::parse(".device 5k\n").unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: CarryOut, lut_index: 0}, to: {type: Lut, lut_index: 1, input_index: 3}}\n",
+      "  - {from: {type: CarryOut, lut_index: 0}, to: {type: Lut, lut_index: 2, input_index: 3}}\n",
+      "  - {from: {type: CarryOut, lut_index: 1}, to: {type: Lut, lut_index: 2, input_index: 2}}\n",
+      "  - {from: {type: CarryOut, lut_index: 2}, to: {type: Lut, lut_index: 0, input_index: 3}}\n",
+      "carry_chains:\n",
+      "  - {luts: [0, 1]}\n",
+      "  - {luts: [1, 3]}\n",
+    )).unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    for expected in [
+      "Carry chain 1: lut_index 3 is out of range",
+      "Carry chain 1: LUT 1 is in more than one carry chain",
+      "Wire 1 (from CarryOut { lut_index: LutIndex(0) } to Lut { lut_index: LutIndex(2), input_index: 3 }): a carry out can only drive input 3 of the next LUT in its chain, LUT 1",
+      "LUT 2 isn't followed by another LUT in a carry chain",
+    ] {
+      assert!(errors.iter().any(|e| e.contains(expected)), "{:?} not in {:#?}", expected, errors);
+    }
+  }
+
+  #[test]
+  fn promote_globals() {
+    let chipdb = ChipDb::parse(".device 5k\n.gbufin\n19 0 7\n6 0 5\n13 0 4\n.gbufpin\n6 0 1 5\n").unwrap();
+    let mut problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios:\n",
+      "  - {spot: {tile: [6, 0], which: 1}, direction: input}\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: Pin, tile: [6, 0], which: 1}, to: {type: Clock, lut_index: 0}}\n",
+      "  - {from: {type: Lut, lut_index: 0}, to: {type: Clock, lut_index: 1}}\n",
+      "  - {from: {type: Global, global_net_index: 7}, to: {type: Clock, lut_index: 2}}\n",
+    )).unwrap();
+    problem.promote_globals(&chipdb).unwrap();
+    // The pin goes onto its dedicated network, and the LUT onto the one that's left, as network 7 is taken.
+    let clock_domains: Vec<_> = problem.lut4s.iter().map(|lut| lut.clock_domain).collect();
+    assert_eq!(clock_domains, [Some(5), Some(4), Some(7)]);
+    let ingresses: Vec<_> = problem.wires.iter().filter_map(|wire| match wire.to {
+      InputSpot::GlobalNetIngress { tile } => Some((wire.from, tile)),
+      _ => None,
+    }).collect();
+    assert_eq!(ingresses, [
+      (OutputSpot::Pin(IoPinSpot { tile: TilePos(6, 0), which: 1 }), TilePos(6, 0)),
+      (OutputSpot::Lut { lut_index: LutIndex(0) }, TilePos(13, 0)),
+    ]);
+
+    let mut problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: Const, value: true}, to: {type: Clock, lut_index: 0}}\n",
+    )).unwrap();
+    problem.fold_constants();
+    let error = problem.promote_globals(&chipdb).unwrap_err();
+    assert!(error.contains("can't be a clock"), "{}", error);
+  }
+
+  #[test]
+  fn global_networks_reach_lut_inputs_through_glb2local() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".logic_tile 1 1\n",
+      ".net 0\n1 1 glb_netwk_3\n",
+      ".net 1\n1 1 glb2local_0\n",
+      ".net 2\n1 1 local_g0_4\n",
+      ".net 3\n1 1 lutff_0/in_0\n",
+      ".buffer 1 1 1 B0[0] B0[1]\n01 0\n",
+      ".buffer 1 1 2 B1[0]\n1 1\n",
+      ".buffer 1 1 3 B2[0]\n1 2\n",
+    )).unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: Global, global_net_index: 3}, to: {type: Lut, lut_index: 0, input_index: 0}}\n",
+    )).unwrap();
+    assert_eq!(problem.validate(&chipdb), Ok(()));
+    let solution = place_and_route(&chipdb, &problem).unwrap();
+    let mut routed: Vec<_> = solution.configured_arcs.iter().map(|arc| chipdb.arcs[arc.arc.0].dest).collect();
+    routed.sort_by_key(|net| net.0);
+    assert_eq!(routed, [ChipNetIndex(1), ChipNetIndex(2), ChipNetIndex(3)]);
+  }
+
+  #[test]
+  fn flip_flops_on_different_clock_edges_get_different_tiles() {
+    let chipdb = ChipDb::parse(".device 5k\n.logic_tile 1 1\n.logic_tile 1 2\n").unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\n",
+      "lut4s:\n",
+      "  - {table: 1, clock_domain: 0}\n",
+      "  - {table: 1, clock_domain: 0, clock_edge: falling}\n",
+      "  - {table: 1, clock_domain: 0}\n",
+      "wires: []\n",
+    )).unwrap();
+    let solution = place_and_route(&chipdb, &problem).unwrap();
+    let tiles: Vec<_> = solution.lut_placements.iter().map(|&(tile, _)| tile).collect();
+    assert_eq!(tiles[0], tiles[2]);
+    assert_ne!(tiles[0], tiles[1]);
+  }
+
+  #[test]
+  fn flip_flops_leave_room_for_every_control_set() {
+    // Two tiles in a column, and a global network ingress near the bottom, the middle and the top of it,
+    // each reachable from every LUT.
+    let mut chipdb = ".device 5k\n.logic_tile 1 1\n.logic_tile 1 9\n".to_string();
+    let lut_outs: Vec<_> = [1, 9].iter().flat_map(|y| (0..8).map(move |k| format!("1 {} lutff_{}/out", y, k))).collect();
+    for (i, lut_out) in lut_outs.iter().enumerate() {
+      chipdb += &format!(".net {}\n{}\n", i, lut_out);
+    }
+    for (i, y) in [0, 7, 10].iter().enumerate() {
+      chipdb += &format!(".net {}\n1 {} fabout\n.buffer 1 {} {} B0[0] B0[1] B0[2] B0[3]\n", 16 + i, y, y, 16 + i);
+      for source in 0..16 {
+        chipdb += &format!("{:04b} {}\n", source, source);
+      }
+    }
+    let chipdb = ChipDb::parse(&chipdb).unwrap();
+    let problem_with_clocks = |clock_domains: &[u32]| -> PnrProblem {
+      let luts: String = clock_domains.iter().map(|n| format!("  - {{table: 1, clock_domain: {}}}\n", n)).collect();
+      let wires: String = [0, 7, 10].iter().zip(0..clock_domains.len())
+        .map(|(y, i)| format!("  - {{from: {{type: Lut, lut_index: {}}}, to: {{type: GlobalNetIngress, tile: [1, {}]}}}}\n", i, y))
+        .collect();
+      serde_yaml::from_str(&format!("used_ios: []\nlut4s:\n{}wires:\n{}", luts, wires)).unwrap()
+    };
+    // The second flip-flop on clock 0 is closer to the empty top tile, but the flip-flop on clock 1 needs that.
+    let problem = problem_with_clocks(&[0, 0, 1]);
+    let solution = place_and_route(&chipdb, &problem).unwrap();
+    let tiles: Vec<_> = solution.lut_placements.iter().map(|&(tile, _)| tile).collect();
+    assert_eq!(tiles, [TilePos(1, 1), TilePos(1, 1), TilePos(1, 9)]);
+
+    let error = place_and_route(&chipdb, &problem_with_clocks(&[0, 1, 2])).unwrap_err();
+    assert!(error.contains("need at least 3 more logic tiles, but only 2 are free"), "{}", error);
+  }
+
+  #[test]
+  fn pin_types_round_trip() {
+    let mut supported = 0;
+    for pin_type in 0..64 {
+      if let Ok((config, has_output)) = IoConfig::from_pin_type(pin_type, false) {
+        assert_eq!(config.pin_type(has_output), pin_type, "PIN_TYPE {:06b}", pin_type);
+        supported += 1;
+      }
+    }
+    // Two input modes without an output, and two by three by three with one.
+    assert_eq!(supported, 2 + 2 * 3 * 3);
+  }
+
+  #[test]
+  fn common_pin_types() {
+    let (config, has_output) = IoConfig::from_pin_type(0b000001, false).unwrap();
+    assert!(!has_output);
+    assert_eq!(config.input_mode, IoMode::Plain);
+    let (config, has_output) = IoConfig::from_pin_type(0b101001, true).unwrap();
+    assert!(has_output && config.pull_up);
+    assert_eq!((config.output_mode, config.output_enable), (IoMode::Plain, OutputEnable::Signal));
+    // Latched inputs and inverted outputs aren't supported.
+    assert!(IoConfig::from_pin_type(0b000011, false).is_err());
+    assert!(IoConfig::from_pin_type(0b011101, false).is_err());
+  }
+
+  #[test]
+  fn bidirectional_pins_may_be_always_driven() {
+    let chipdb = ChipDb::parse(".device 5k\n.net 0\n1 0 io_0/D_IN_0\n.net 1\n1 0 io_0/D_OUT_0\n").unwrap();
+    for output_enable in ["always", "signal"] {
+      let problem: PnrProblem = serde_yaml::from_str(&format!(
+        "used_ios:\n  - {{spot: {{tile: [1, 0], which: 0}}, direction: bidirectional, config: {{output_enable: {}}}}}\nlut4s: []\nwires: []\n",
+        output_enable,
+      )).unwrap();
+      assert_eq!(problem.validate(&chipdb), Ok(()), "output_enable: {}", output_enable);
+    }
+  }
+
+  #[test]
+  fn ram_pin_names() {
+    for name in ["RDATA_15", "RADDR_10", "RCLK", "RCLKE", "RE", "WDATA_0", "MASK_7", "WADDR_3", "WCLK", "WCLKE", "WE"] {
+      assert_eq!(RamPin::try_from(name.to_string()).unwrap().to_string(), name);
+    }
+    assert!(RamPin::try_from("RDATA_16".to_string()).unwrap_err().contains("out of range"));
+    assert!(RamPin::try_from("RADDR_11".to_string()).is_err());
+    assert!(RamPin::try_from("WFOO".to_string()).is_err());
+  }
+
+  #[test]
+  fn dsp_parameters_are_checked() {
+    let chipdb = ChipDb::parse(".device 5k\n.extra_cell 0 5 MAC16\nCLK 0 5 mult/clk\n").unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\n",
+      "wires:\n  - {from: {type: Dsp, dsp_index: 0, port: O_3}, to: {type: Dsp, dsp_index: 1, port: CLK}}\n",
+      "dsps:\n  - {parameters: {A_SIGNED: 1, TOPOUTPUT_SELECT: 4, FOO: 1}}\n  - {}\n",
+    )).unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    for expected in [
+      "Too many DSPs: 2 > 1",
+      "DSP 0: unknown parameter FOO",
+      "DSP 0: TOPOUTPUT_SELECT is 2 bits wide, so 4 is out of range",
+      "a DSP has no port O_3",
+    ] {
+      assert!(errors.iter().any(|e| e.contains(expected)), "{:?} not in {:#?}", expected, errors);
+    }
+    assert!(is_mac16_output(PortName::try_from("O_31").unwrap()));
+    assert!(!is_mac16_output(PortName::try_from("CLK").unwrap()));
+  }
+
+  #[test]
+  fn extra_cells_nearest_the_middle_go_first() {
+    let chipdb = ChipDb::parse(concat!(
+      ".device 5k\n",
+      ".extra_cell 0 0 1 SPRAM\n.extra_cell 0 0 2 SPRAM\n",
+      ".extra_cell 25 0 1 SPRAM\n.extra_cell 0 5 MAC16\n",
+    )).unwrap();
+    assert_eq!(nearest_extra_cells(&chipdb, "SPRAM", 1, (24.0, 1.0)), Ok(vec![2]));
+    assert_eq!(nearest_extra_cells(&chipdb, "SPRAM", 3, (24.0, 1.0)).map(|sites| sites[0]), Ok(2));
+    assert_eq!(nearest_extra_cells(&chipdb, "SPRAM", 4, (24.0, 1.0)), Err(3));
+    assert_eq!(nearest_extra_cells(&chipdb, "MAC16", 1, (0.0, 0.0)), Ok(vec![3]));
+    assert!(is_spram_output(PortName::try_from("DATAOUT_15").unwrap()));
+    assert!(!is_spram_output(PortName::try_from("DATAIN_15").unwrap()));
+  }
+
+  #[test]
+  fn oscillators_are_enabled_unless_wired() {
+    let chipdb = ChipDb::parse(".device 5k\n.extra_cell 0 0 31 HFOSC\nCLKHF 0 31 glb_netwk_4\nCLKHFEN 0 31 lutff_0/in_0\n").unwrap();
+    let mut problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\n",
+      "lut4s:\n  - {table: 1, clock_domain: null}\n",
+      "wires:\n",
+      "  - {from: {type: HfOsc, divider: 1}, to: {type: Clock, lut_index: 0}}\n",
+      "  - {from: {type: HfOsc, divider: 4}, to: {type: Lut, lut_index: 0, input_index: 0}}\n",
+      "  - {from: {type: LfOsc}, to: {type: Lut, lut_index: 0, input_index: 1}}\n",
+      "  - {from: {type: Lut, lut_index: 0}, to: {type: HfOsc, port: CLKHF}}\n",
+    )).unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    for expected in [
+      "divider 4 is out of range (0 to 3)",
+      "the HFOSC is already used with divider 1",
+      "No LFOSC in the chipdb",
+      "HFOSC port CLKHF is an output",
+    ] {
+      assert!(errors.iter().any(|e| e.contains(expected)), "{:?} not in {:#?}", expected, errors);
+    }
+    problem.wires.pop();
+    problem.wires.push(Wire { from: OutputSpot::Lut { lut_index: LutIndex(0) }, to: InputSpot::HfOsc { port: PortName::try_from("CLKHFEN").unwrap() } });
+    problem.fold_constants();
+    let tied_high = |to: InputSpot| problem.wires.iter().any(|wire| wire.to == to && wire.from != OutputSpot::Lut { lut_index: LutIndex(0) });
+    assert!(!tied_high(InputSpot::HfOsc { port: PortName::try_from("CLKHFEN").unwrap() }));
+    assert!(tied_high(InputSpot::HfOsc { port: PortName::try_from("CLKHFPU").unwrap() }));
+    assert!(tied_high(InputSpot::LfOsc { port: PortName::try_from("CLKLFEN").unwrap() }));
+  }
+
+  #[test]
+  fn pll_dividers_match_icepll() {
+    // What `icepll -i 12 -o 48`, `-o 96` and `-o 100` print.
+    assert_eq!(PllDividers::for_frequencies(12.0, 48.0), Ok(PllDividers { divr: 0, divf: 63, divq: 4, filter_range: 1 }));
+    assert_eq!(PllDividers::for_frequencies(12.0, 96.0), Ok(PllDividers { divr: 0, divf: 63, divq: 3, filter_range: 1 }));
+    assert_eq!(PllDividers::for_frequencies(12.0, 100.0), Ok(PllDividers { divr: 0, divf: 66, divq: 3, filter_range: 1 }));
+    let dividers = PllDividers::for_frequencies(12.0, 100.0).unwrap();
+    assert_eq!(dividers.output_mhz(12.0), 100.5);
+  }
+
+  #[test]
+  fn pll_dividers_keep_the_phase_detector_in_range() {
+    // A 40 MHz reference goes straight into the phase detector, which puts it in the third filter range.
+    let dividers = PllDividers::for_frequencies(40.0, 80.0).unwrap();
+    assert_eq!((dividers.divr, dividers.filter_range), (0, 3));
+    assert_eq!(dividers.output_mhz(40.0), 80.0);
+  }
+
+  #[test]
+  fn pll_frequencies_out_of_range() {
+    assert!(PllDividers::for_frequencies(5.0, 48.0).is_err());
+    assert!(PllDividers::for_frequencies(200.0, 48.0).is_err());
+    assert!(PllDividers::for_frequencies(12.0, 10.0).is_err());
+    assert!(PllDividers::for_frequencies(12.0, 300.0).is_err());
+  }
+
+  #[test]
+  fn led_drivers_are_checked() {
+    let chipdb = ChipDb::parse(".device 5k\n.extra_cell 0 0 31 LEDDA_IP\nLEDDCLK 0 31 lutff_0/in_1\nPWMOUT0 0 31 slf_op_0\n").unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\n",
+      "wires:\n",
+      "  - {from: {type: LedDriver, port: PWMOUT0}, to: {type: RgbDriver, port: RGB0PWM}}\n",
+      "  - {from: {type: LedDriver, port: LEDDCLK}, to: {type: LedDriver, port: PWMOUT0}}\n",
+    )).unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    for expected in [
+      "the RGB driver has to be configured with rgb_driver to use it",
+      "No RGBA_DRV in the chipdb",
+      "LEDDA_IP port LEDDCLK is an input",
+      "LEDDA_IP port PWMOUT0 is an output",
+    ] {
+      assert!(errors.iter().any(|e| e.contains(expected)), "{:?} not in {:#?}", expected, errors);
+    }
+    let problem: PnrProblem = serde_yaml::from_str("used_ios: []\nlut4s: []\nwires: []\nrgb_driver: {rgb0_current: 0b000111, rgb1_current: 0b000101}\n").unwrap();
+    let errors = problem.validate(&chipdb).unwrap_err();
+    assert!(errors.iter().any(|e| e.contains("rgb1_current must be 0, or 1 to 6 bits set from the bottom")), "{:#?}", errors);
+    assert!(!errors.iter().any(|e| e.contains("rgb0_current")), "{:#?}", errors);
+  }
+
+  #[test]
+  fn hard_ips_take_the_site_they_ask_for() {
+    let chipdb = ChipDb::parse(".device 5k\n.extra_cell 25 0 31 I2C\n.extra_cell 0 0 31 I2C\n.extra_cell 0 0 0 SPI\n").unwrap();
+    let problem: PnrProblem = serde_yaml::from_str(concat!(
+      "used_ios: []\nlut4s: []\nwires: []\n",
+      "hard_ips:\n  - {cell_type: I2C}\n  - {cell_type: I2C, site: 0}\n  - {cell_type: SPI}\n",
+    )).unwrap();
+    // The left I2C block is the one at x = 0, which comes second in the chipdb.
+    assert_eq!(problem.hard_ip_sites(&chipdb), Ok(vec![0, 1, 2]));
+    let problem: PnrProblem = serde_yaml::from_str("used_ios: []\nlut4s: []\nwires: []\nhard_ips:\n  - {cell_type: SPI}\n  - {cell_type: SPI}\n").unwrap();
+    assert!(problem.hard_ip_sites(&chipdb).unwrap_err().contains("no SPI cell left (there are 1)"));
+    assert!(is_hard_ip_output(PortName::try_from("SBDATO_7").unwrap()));
+    assert!(is_hard_ip_output(PortName::try_from("SCLOE").unwrap()));
+    assert!(!is_hard_ip_output(PortName::try_from("SBADRI_3").unwrap()));
+  }
+}
@@ -0,0 +1,481 @@
+use std::{collections::HashMap, marker::PhantomData};
+
+use serde::{Deserialize, Deserializer, de};
+
+use crate::pnr::{CarryChain, Dsp, DspIndex, HardIp, HardIpIndex, InputSpot, IoConfig, IoDirection, IoPinSpot, Lut4, LutIndex, OutputSpot, Pll, PllDividers, PllIndex, PllReference, PnrProblem, PortName, Ram, RamIndex, RamPin, RgbDriver, Spram, SpramIndex, UsedIo, Wire};
+
+/// An IO as written in an input file. The spot may be left out if the IO is named,
+/// in which case it comes from the pin constraints instead.
+#[derive(Debug, Deserialize)]
+pub struct IoDecl {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub spot: Option<IoPinSpot>,
+  pub direction: IoDirection,
+  #[serde(default)]
+  pub config: IoConfig,
+}
+
+/// Either a spot, written as a map with its type, or the name of something.
+#[derive(Debug)]
+pub enum SpotRef<T> {
+  Spot(T),
+  Name(String),
+}
+
+// Not #[serde(untagged)], which would hide why a malformed spot didn't parse behind "data did not match any variant".
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SpotRef<T> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    struct Visitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> de::Visitor<'de> for Visitor<T> {
+      type Value = SpotRef<T>;
+
+      fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a spot or a name")
+      }
+
+      fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+        Ok(SpotRef::Name(name.to_string()))
+      }
+
+      fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(de::value::MapAccessDeserializer::new(map)).map(SpotRef::Spot)
+      }
+    }
+
+    deserializer.deserialize_any(Visitor(PhantomData))
+  }
+}
+
+/// Something that drives a net: either a spot, the name of a LUT or input or bidirectional IO,
+/// `<lut name>.cout` for the carry out of a LUT in a carry chain, `<io name>.ddr` for the falling edge input of a DDR pin,
+/// `<ram name>.RDATA_<k>` for a RAM's read data, `<dsp name>.<port>` for a DSP's output, like `<dsp name>.O_3`,
+/// `<spram name>.DATAOUT_<k>` for an SPRAM's read data, `<pll name>.<port>` for a PLL's output, like `<pll name>.PLLOUT_A`,
+/// or `<hard IP name>.<port>` for an output of a hard IP block, like `<i2c name>.SBDATO0`.
+pub type OutputRef = SpotRef<OutputSpot>;
+
+/// Something a net drives: either a spot, the name of an output or bidirectional IO, `<lut name>.in<k>`, or
+/// `<lut name>.clk`, `<lut name>.en` and `<lut name>.sr` for the clock, clock enable and set/reset of the LUT's flip-flop.
+/// An output IO also has `<io name>.ddr` for its falling edge output and `<io name>.oe` for its output enable,
+/// and any IO has `<io name>.inclk`, `<io name>.outclk` and `<io name>.cen` for the register controls of its IO tile.
+/// The inputs of RAMs, DSPs and SPRAMs are named after the ports of SB_RAM40_4K, SB_MAC16 and SB_SPRAM256KA,
+/// like `<ram name>.WADDR_3`, `<dsp name>.CLK` or `<spram name>.ADDRESS_7`, and those of PLLs like `<pll name>.REFERENCECLK`.
+/// The RGB driver's are `<rgb driver name>.RGB0PWM` and so on, and those of hard IP blocks are named after the
+/// ports of the chipdb's cell, like `<spi name>.SBCLKI`.
+pub type InputRef = SpotRef<InputSpot>;
+
+#[derive(Debug, Deserialize)]
+pub struct NamedWire {
+  pub from: OutputRef,
+  pub to: InputRef,
+}
+
+/// A LUT, either by index or by name.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum LutRef {
+  Index(LutIndex),
+  Name(String),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CarryChainDecl {
+  pub luts: Vec<LutRef>,
+  #[serde(default)]
+  pub carry_in: bool,
+}
+
+/// A net with a single driver and any number of sinks.
+#[derive(Debug, Deserialize)]
+pub struct Net {
+  #[serde(default)]
+  pub name: Option<String>,
+  pub driver: OutputRef,
+  pub sinks: Vec<InputRef>,
+}
+
+/// How a PLL's dividers are given: either directly, or as the frequencies to pick them for.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PllSettings {
+  Dividers(PllDividers),
+  Frequencies { input_mhz: f64, output_mhz: f64 },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PllDecl {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub reference: PllReference,
+  #[serde(flatten)]
+  pub settings: PllSettings,
+}
+
+/// The input file format, which is a PnrProblem that may refer to LUTs and IOs by name.
+#[derive(Debug, Deserialize)]
+pub struct ProblemFile {
+  #[serde(default)]
+  pub used_ios: Vec<IoDecl>,
+  #[serde(default)]
+  pub lut4s: Vec<Lut4>,
+  #[serde(default)]
+  pub wires: Vec<NamedWire>,
+  #[serde(default)]
+  pub nets: Vec<Net>,
+  #[serde(default)]
+  pub carry_chains: Vec<CarryChainDecl>,
+  #[serde(default)]
+  pub rams: Vec<Ram>,
+  #[serde(default)]
+  pub dsps: Vec<Dsp>,
+  #[serde(default)]
+  pub sprams: Vec<Spram>,
+  #[serde(default)]
+  pub plls: Vec<PllDecl>,
+  #[serde(default)]
+  pub rgb_driver: Option<RgbDriver>,
+  #[serde(default)]
+  pub hard_ips: Vec<HardIp>,
+}
+
+enum Named {
+  Lut(LutIndex),
+  Io(IoPinSpot, IoDirection),
+  Ram(RamIndex),
+  Dsp(DspIndex),
+  Spram(SpramIndex),
+  Pll(PllIndex),
+  RgbDriver,
+  HardIp(HardIpIndex),
+}
+
+struct Resolver {
+  names: HashMap<String, Named>,
+}
+
+impl Resolver {
+  fn lut(&self, lut: &LutRef) -> Result<LutIndex, String> {
+    match lut {
+      LutRef::Index(lut_index) => Ok(*lut_index),
+      LutRef::Name(name) => match self.names.get(name) {
+        Some(&Named::Lut(lut_index)) => Ok(lut_index),
+        Some(Named::Io(..)) => Err(format!("Expected a LUT, but {} is an IO", name)),
+        Some(Named::Ram(_)) => Err(format!("Expected a LUT, but {} is a RAM", name)),
+        Some(Named::Dsp(_)) => Err(format!("Expected a LUT, but {} is a DSP", name)),
+        Some(Named::Spram(_)) => Err(format!("Expected a LUT, but {} is an SPRAM", name)),
+        Some(Named::Pll(_)) => Err(format!("Expected a LUT, but {} is a PLL", name)),
+        Some(Named::RgbDriver) => Err(format!("Expected a LUT, but {} is the RGB driver", name)),
+        Some(Named::HardIp(_)) => Err(format!("Expected a LUT, but {} is a hard IP block", name)),
+        None => Err(format!("Unknown LUT: {}", name)),
+      },
+    }
+  }
+
+  fn output(&self, output: &OutputRef) -> Result<OutputSpot, String> {
+    let name = match output {
+      SpotRef::Spot(spot) => return Ok(*spot),
+      SpotRef::Name(name) => name,
+    };
+    if let Some(lut_name) = name.strip_suffix(".cout")
+      && let Some(&Named::Lut(lut_index)) = self.names.get(lut_name)
+    {
+      return Ok(OutputSpot::CarryOut { lut_index });
+    }
+    if let Some(io_name) = name.strip_suffix(".ddr")
+      && let Some(&Named::Io(spot, _)) = self.names.get(io_name)
+    {
+      return Ok(OutputSpot::PinDdrIn(spot));
+    }
+    if let Some((ram_name, pin)) = name.split_once('.')
+      && let Some(&Named::Ram(ram_index)) = self.names.get(ram_name)
+    {
+      let pin = RamPin::try_from(pin.to_string())?;
+      return Ok(OutputSpot::Ram { ram_index, pin });
+    }
+    if let Some((dsp_name, port)) = name.split_once('.')
+      && let Some(&Named::Dsp(dsp_index)) = self.names.get(dsp_name)
+    {
+      return Ok(OutputSpot::Dsp { dsp_index, port: PortName::try_from(port)? });
+    }
+    if let Some((spram_name, port)) = name.split_once('.')
+      && let Some(&Named::Spram(spram_index)) = self.names.get(spram_name)
+    {
+      return Ok(OutputSpot::Spram { spram_index, port: PortName::try_from(port)? });
+    }
+    if let Some((pll_name, port)) = name.split_once('.')
+      && let Some(&Named::Pll(pll_index)) = self.names.get(pll_name)
+    {
+      return Ok(OutputSpot::Pll { pll_index, port: PortName::try_from(port)? });
+    }
+    if let Some((hard_ip_name, port)) = name.split_once('.')
+      && let Some(&Named::HardIp(hard_ip_index)) = self.names.get(hard_ip_name)
+    {
+      return Ok(OutputSpot::HardIp { hard_ip_index, port: PortName::try_from(port)? });
+    }
+    match self.names.get(name) {
+      Some(&Named::Lut(lut_index)) => Ok(OutputSpot::Lut { lut_index }),
+      Some(&Named::Io(spot, direction)) if direction.has_input() => Ok(OutputSpot::Pin(spot)),
+      Some(Named::Io(..)) => Err(format!("IO {} is an output, so it can't drive anything", name)),
+      Some(Named::Ram(_)) => Err(format!("Expected a RAM pin like {}.RDATA_0, got {}", name, name)),
+      Some(Named::Dsp(_)) => Err(format!("Expected a DSP port like {}.O_0, got {}", name, name)),
+      Some(Named::Spram(_)) => Err(format!("Expected an SPRAM port like {}.DATAOUT_0, got {}", name, name)),
+      Some(Named::Pll(_)) => Err(format!("Expected a PLL port like {}.PLLOUT_A, got {}", name, name)),
+      Some(Named::RgbDriver) => Err(format!("The RGB driver {} has no outputs", name)),
+      Some(Named::HardIp(_)) => Err(format!("Expected a hard IP port like {}.SBDATO0, got {}", name, name)),
+      None => Err(format!("Unknown driver: {}", name)),
+    }
+  }
+
+  fn input(&self, input: &InputRef) -> Result<InputSpot, String> {
+    let name = match input {
+      SpotRef::Spot(spot) => return Ok(*spot),
+      SpotRef::Name(name) => name,
+    };
+    if let Some((lut_name, input_index)) = name.rsplit_once(".in")
+      && let (Some(&Named::Lut(lut_index)), Ok(input_index)) = (self.names.get(lut_name), input_index.parse())
+    {
+      return Ok(InputSpot::Lut { lut_index, input_index });
+    }
+    if let Some((lut_name, suffix)) = name.rsplit_once('.')
+      && let Some(&Named::Lut(lut_index)) = self.names.get(lut_name)
+    {
+      match suffix {
+        "en" => return Ok(InputSpot::ClockEnable { lut_index }),
+        "sr" => return Ok(InputSpot::SetReset { lut_index }),
+        "clk" => return Ok(InputSpot::Clock { lut_index }),
+        _ => {}
+      }
+    }
+    if let Some((io_name, suffix)) = name.rsplit_once('.')
+      && let Some(&Named::Io(spot, _)) = self.names.get(io_name)
+    {
+      match suffix {
+        "ddr" => return Ok(InputSpot::PinDdrOut(spot)),
+        "oe" => return Ok(InputSpot::PinOutputEnable(spot)),
+        "inclk" => return Ok(InputSpot::IoInputClock { tile: spot.tile }),
+        "outclk" => return Ok(InputSpot::IoOutputClock { tile: spot.tile }),
+        "cen" => return Ok(InputSpot::IoClockEnable { tile: spot.tile }),
+        _ => {}
+      }
+    }
+    if let Some((ram_name, pin)) = name.split_once('.')
+      && let Some(&Named::Ram(ram_index)) = self.names.get(ram_name)
+    {
+      let pin = RamPin::try_from(pin.to_string())?;
+      return Ok(InputSpot::Ram { ram_index, pin });
+    }
+    if let Some((dsp_name, port)) = name.split_once('.')
+      && let Some(&Named::Dsp(dsp_index)) = self.names.get(dsp_name)
+    {
+      return Ok(InputSpot::Dsp { dsp_index, port: PortName::try_from(port)? });
+    }
+    if let Some((spram_name, port)) = name.split_once('.')
+      && let Some(&Named::Spram(spram_index)) = self.names.get(spram_name)
+    {
+      return Ok(InputSpot::Spram { spram_index, port: PortName::try_from(port)? });
+    }
+    if let Some((pll_name, port)) = name.split_once('.')
+      && let Some(&Named::Pll(pll_index)) = self.names.get(pll_name)
+    {
+      return Ok(InputSpot::Pll { pll_index, port: PortName::try_from(port)? });
+    }
+    if let Some((rgb_driver_name, port)) = name.split_once('.')
+      && let Some(Named::RgbDriver) = self.names.get(rgb_driver_name)
+    {
+      return Ok(InputSpot::RgbDriver { port: PortName::try_from(port)? });
+    }
+    if let Some((hard_ip_name, port)) = name.split_once('.')
+      && let Some(&Named::HardIp(hard_ip_index)) = self.names.get(hard_ip_name)
+    {
+      return Ok(InputSpot::HardIp { hard_ip_index, port: PortName::try_from(port)? });
+    }
+    match self.names.get(name) {
+      Some(&Named::Io(spot, direction)) if direction.has_output() => Ok(InputSpot::Pin(spot)),
+      Some(Named::Io(..)) => Err(format!("IO {} is an input, so it can't be driven", name)),
+      Some(Named::Lut(_)) => Err(format!("Expected a LUT input like {}.in0, got {}", name, name)),
+      Some(Named::Ram(_)) => Err(format!("Expected a RAM pin like {}.WDATA_0, got {}", name, name)),
+      Some(Named::Dsp(_)) => Err(format!("Expected a DSP port like {}.A_0, got {}", name, name)),
+      Some(Named::Spram(_)) => Err(format!("Expected an SPRAM port like {}.ADDRESS_0, got {}", name, name)),
+      Some(Named::Pll(_)) => Err(format!("Expected a PLL port like {}.REFERENCECLK, got {}", name, name)),
+      Some(Named::RgbDriver) => Err(format!("Expected an RGB driver port like {}.RGB0PWM, got {}", name, name)),
+      Some(Named::HardIp(_)) => Err(format!("Expected a hard IP port like {}.SBCLKI, got {}", name, name)),
+      None => Err(format!("Unknown sink: {}", name)),
+    }
+  }
+}
+
+impl ProblemFile {
+  /// Turns names into indices and spots, producing the form that place_and_route works on.
+  /// IOs without an explicit spot are looked up by name in `port_spots`.
+  pub fn resolve(self, port_spots: &HashMap<String, IoPinSpot>) -> Result<PnrProblem, String> {
+    let mut resolver = Resolver { names: HashMap::new() };
+    let mut problem = PnrProblem::new();
+
+    for IoDecl { name, spot, direction, config } in self.used_ios {
+      let constrained_spot = name.as_ref().and_then(|name| port_spots.get(name).copied());
+      let spot = match (spot, constrained_spot) {
+        (Some(spot), Some(constrained_spot)) if spot != constrained_spot => {
+          return Err(format!(
+            "IO {} is at {:?} in the input file, but constrained to {:?}",
+            name.unwrap(), spot, constrained_spot,
+          ));
+        }
+        (Some(spot), _) | (None, Some(spot)) => spot,
+        (None, None) => match &name {
+          Some(name) => return Err(format!("IO {} has no spot, and isn't constrained to a pin", name)),
+          None => return Err("Unnamed IO has no spot".to_string()),
+        },
+      };
+      if let Some(name) = &name && resolver.names.insert(name.clone(), Named::Io(spot, direction)).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.used_ios.push(UsedIo { name, spot, direction, config });
+    }
+
+    for (i, lut) in self.lut4s.into_iter().enumerate() {
+      if let Some(name) = &lut.name && resolver.names.insert(name.clone(), Named::Lut(LutIndex(i))).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.lut4s.push(lut);
+    }
+
+    for (i, ram) in self.rams.into_iter().enumerate() {
+      if let Some(name) = &ram.name && resolver.names.insert(name.clone(), Named::Ram(RamIndex(i))).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.rams.push(ram);
+    }
+
+    for (i, dsp) in self.dsps.into_iter().enumerate() {
+      if let Some(name) = &dsp.name && resolver.names.insert(name.clone(), Named::Dsp(DspIndex(i))).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.dsps.push(dsp);
+    }
+
+    for (i, spram) in self.sprams.into_iter().enumerate() {
+      if let Some(name) = &spram.name && resolver.names.insert(name.clone(), Named::Spram(SpramIndex(i))).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.sprams.push(spram);
+    }
+
+    for (i, PllDecl { name, reference, settings }) in self.plls.into_iter().enumerate() {
+      if let Some(name) = &name && resolver.names.insert(name.clone(), Named::Pll(PllIndex(i))).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      let dividers = match settings {
+        PllSettings::Dividers(dividers) => dividers,
+        PllSettings::Frequencies { input_mhz, output_mhz } => {
+          let dividers = PllDividers::for_frequencies(input_mhz, output_mhz).map_err(|e| format!("PLL {}: {}", i, e))?;
+          println!("PLL {}: {:?} makes {} MHz from {} MHz", i, dividers, dividers.output_mhz(input_mhz), input_mhz);
+          dividers
+        }
+      };
+      problem.plls.push(Pll { name, reference, dividers });
+    }
+
+    if let Some(rgb_driver) = self.rgb_driver {
+      if let Some(name) = &rgb_driver.name && resolver.names.insert(name.clone(), Named::RgbDriver).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.rgb_driver = Some(rgb_driver);
+    }
+
+    for (i, hard_ip) in self.hard_ips.into_iter().enumerate() {
+      if let Some(name) = &hard_ip.name && resolver.names.insert(name.clone(), Named::HardIp(HardIpIndex(i))).is_some() {
+        return Err(format!("Duplicate name: {}", name));
+      }
+      problem.hard_ips.push(hard_ip);
+    }
+
+    for (i, NamedWire { from, to }) in self.wires.iter().enumerate() {
+      let context = |e| format!("Wire {}: {}", i, e);
+      let from = resolver.output(from).map_err(context)?;
+      let to = resolver.input(to).map_err(context)?;
+      problem.wires.push(Wire { from, to });
+    }
+
+    for (i, net) in self.nets.iter().enumerate() {
+      let context = |e| match &net.name {
+        Some(name) => format!("Net {}: {}", name, e),
+        None => format!("Net {}: {}", i, e),
+      };
+      let from = resolver.output(&net.driver).map_err(context)?;
+      for sink in &net.sinks {
+        let to = resolver.input(sink).map_err(context)?;
+        problem.wires.push(Wire { from, to });
+      }
+    }
+
+    for (i, chain) in self.carry_chains.iter().enumerate() {
+      let luts = chain.luts.iter()
+        .map(|lut| resolver.lut(lut))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Carry chain {}: {}", i, e))?;
+      problem.carry_chains.push(CarryChain { luts, carry_in: chain.carry_in });
+    }
+
+    Ok(problem)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn wires_take_spots_or_names() {
+    let yaml = "lut4s:\n  - {name: a, table: 1}\nwires:\n  - {from: a, to: {type: Lut, lut_index: 0, input_index: 1}}\n  - {from: {type: Lut, lut_index: 0}, to: a.in2}\n";
+    let problem_file: ProblemFile = serde_yaml::from_str(yaml).unwrap();
+    let problem = problem_file.resolve(&HashMap::new()).unwrap();
+    assert_eq!(problem.wires[0].from, OutputSpot::Lut { lut_index: LutIndex(0) });
+    assert!(matches!(problem.wires[0].to, InputSpot::Lut { lut_index: LutIndex(0), input_index: 1 }));
+    assert!(matches!(problem.wires[1].to, InputSpot::Lut { lut_index: LutIndex(0), input_index: 2 }));
+  }
+
+  #[test]
+  fn malformed_spots_say_what_is_wrong() {
+    let yaml = "wires:\n  - {from: {type: Lut, lut_indx: 0}, to: {type: Lut, lut_index: 0, input_index: 1}}\n";
+    let error = serde_yaml::from_str::<ProblemFile>(yaml).unwrap_err().to_string();
+    assert!(error.contains("lut_index"), "{}", error);
+    let yaml = "wires:\n  - {from: {type: Lut, lut_index: 0}, to: {type: Nowhere}}\n";
+    let error = serde_yaml::from_str::<ProblemFile>(yaml).unwrap_err().to_string();
+    assert!(error.contains("Nowhere"), "{}", error);
+  }
+
+  #[test]
+  fn carry_chains_take_lut_names() {
+    let yaml = concat!(
+      "lut4s:\n  - {name: a, table: 1}\n  - {name: b, table: 1}\n",
+      "wires:\n  - {from: a.cout, to: b.in3}\n",
+      "carry_chains:\n  - {luts: [a, b], carry_in: true}\n",
+    );
+    let problem_file: ProblemFile = serde_yaml::from_str(yaml).unwrap();
+    let problem = problem_file.resolve(&HashMap::new()).unwrap();
+    assert_eq!(problem.wires[0].from, OutputSpot::CarryOut { lut_index: LutIndex(0) });
+    assert!(matches!(problem.wires[0].to, InputSpot::Lut { lut_index: LutIndex(1), input_index: 3 }));
+    assert_eq!(problem.carry_chains[0].luts, vec![LutIndex(0), LutIndex(1)]);
+    assert!(problem.carry_chains[0].carry_in);
+  }
+
+  #[test]
+  fn pll_frequencies_pick_dividers() {
+    let yaml = "plls:\n  - {name: fast, input_mhz: 12, output_mhz: 48}\n  - {name: slow, divr: 0, divf: 31, divq: 5, filter_range: 1}\n";
+    let problem_file: ProblemFile = serde_yaml::from_str(yaml).unwrap();
+    let problem = problem_file.resolve(&HashMap::new()).unwrap();
+    assert_eq!(problem.plls[0].dividers, PllDividers { divr: 0, divf: 63, divq: 4, filter_range: 1 });
+    assert_eq!(problem.plls[1].dividers, PllDividers { divr: 0, divf: 31, divq: 5, filter_range: 1 });
+  }
+
+  #[test]
+  fn pll_frequencies_out_of_range() {
+    let yaml = "plls:\n  - {input_mhz: 12, output_mhz: 500}\n";
+    let problem_file: ProblemFile = serde_yaml::from_str(yaml).unwrap();
+    let error = problem_file.resolve(&HashMap::new()).unwrap_err();
+    assert!(error.starts_with("PLL 0: "), "{}", error);
+  }
+}
@@ -0,0 +1,844 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Deserialize;
+
+use crate::pnr::{CarryChain, ClockEdge, Dsp, DspIndex, HardIp, HardIpIndex, InputSpot, IoConfig, IoDirection, IoMode, IoPinSpot, is_fixed_cell_output, is_hard_ip_output, is_pll_output, Lut4, LutIndex, MAC16_PARAMETERS, OutputEnable, OutputSpot, Pll, PllDividers, PllIndex, PllReference, PnrProblem, PortName, Ram, RamIndex, RamMode, RamPin, ResetMode, ResetValue, RgbCurrentMode, RgbDriver, Spram, SpramIndex, UsedIo, Wire};
+
+#[derive(Debug, Deserialize)]
+struct YosysJson {
+  modules: BTreeMap<String, YosysModule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YosysModule {
+  #[serde(default)]
+  attributes: HashMap<String, serde_json::Value>,
+  #[serde(default)]
+  ports: BTreeMap<String, YosysPort>,
+  #[serde(default)]
+  cells: BTreeMap<String, YosysCell>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YosysPort {
+  direction: String,
+  bits: Vec<YosysBit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YosysCell {
+  #[serde(rename = "type")]
+  cell_type: String,
+  #[serde(default)]
+  parameters: HashMap<String, serde_json::Value>,
+  #[serde(default)]
+  connections: HashMap<String, Vec<YosysBit>>,
+}
+
+/// Yosys writes each bit either as a net number, or as one of the strings "0", "1", "x" or "z".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+enum YosysBit {
+  Net(usize),
+  #[serde(deserialize_with = "deserialize_constant_bit")]
+  Const(bool),
+}
+
+fn deserialize_constant_bit<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+  let s = String::deserialize(deserializer)?;
+  match s.as_str() {
+    "1" => Ok(true),
+    // We treat undefined and high-impedance bits as zero.
+    "0" | "x" | "z" => Ok(false),
+    _ => Err(serde::de::Error::custom(format!("Invalid constant bit: {:?}", s))),
+  }
+}
+
+/// The name we use for bit `index` of a port, as it would be written in a constraint file.
+pub fn port_bit_name(port: &str, width: usize, index: usize) -> String {
+  match width {
+    1 => port.to_string(),
+    _ => format!("{}[{}]", port, index),
+  }
+}
+
+fn get_top_module(json: &YosysJson) -> Result<(&String, &YosysModule), String> {
+  let is_top = |module: &YosysModule| match module.attributes.get("top") {
+    Some(serde_json::Value::String(s)) => s.contains('1'),
+    Some(serde_json::Value::Number(n)) => n.as_u64() != Some(0),
+    _ => false,
+  };
+  let tops: Vec<_> = json.modules.iter().filter(|(_, m)| is_top(m)).collect();
+  match (&tops[..], json.modules.len()) {
+    ([top], _) => Ok(*top),
+    ([], 1) => Ok(json.modules.iter().next().unwrap()),
+    ([], _) => Err("No top module found in netlist".to_string()),
+    _ => Err(format!("Multiple top modules found in netlist: {:?}", tops.iter().map(|(name, _)| name).collect::<Vec<_>>())),
+  }
+}
+
+fn get_param(cell_name: &str, cell: &YosysCell, param: &str) -> Result<u64, String> {
+  match cell.parameters.get(param) {
+    Some(serde_json::Value::Number(n)) => n.as_u64().ok_or_else(|| format!("Cell {}: invalid {}: {}", cell_name, param, n)),
+    // Yosys writes bit vectors as strings of binary digits. String parameters like the CLKHF_DIV of SB_HFOSC are written "0b01".
+    Some(serde_json::Value::String(s)) => u64::from_str_radix(s.strip_prefix("0b").unwrap_or(s), 2).map_err(|_| format!("Cell {}: invalid {}: {:?}", cell_name, param, s)),
+    Some(v) => Err(format!("Cell {}: invalid {}: {}", cell_name, param, v)),
+    None => Err(format!("Cell {}: missing parameter {}", cell_name, param)),
+  }
+}
+
+/// Reads a string parameter, like the FEEDBACK_PATH of SB_PLL40_CORE.
+fn get_string_param<'a>(cell: &'a YosysCell, param: &str) -> Option<&'a str> {
+  match cell.parameters.get(param) {
+    Some(serde_json::Value::String(s)) => Some(s.trim_end()),
+    _ => None,
+  }
+}
+
+fn get_param_or_default(cell_name: &str, cell: &YosysCell, param: &str) -> Result<u64, String> {
+  match cell.parameters.contains_key(param) {
+    true => get_param(cell_name, cell, param),
+    false => Ok(0),
+  }
+}
+
+/// Reads a parameter too wide for a u64, like the INIT_0 to INIT_F of SB_RAM40_4K, least significant bit first.
+fn get_wide_param(cell_name: &str, cell: &YosysCell, param: &str) -> Result<Vec<bool>, String> {
+  match cell.parameters.get(param) {
+    Some(serde_json::Value::String(s)) => s.chars().rev().map(|c| match c {
+      '0' | 'x' => Ok(false),
+      '1' => Ok(true),
+      _ => Err(format!("Cell {}: invalid {}: {:?}", cell_name, param, s)),
+    }).collect(),
+    Some(serde_json::Value::Number(n)) => {
+      let value = n.as_u64().ok_or_else(|| format!("Cell {}: invalid {}: {}", cell_name, param, n))?;
+      Ok((0..64).map(|i| (value >> i) & 1 != 0).collect())
+    }
+    Some(v) => Err(format!("Cell {}: invalid {}: {}", cell_name, param, v)),
+    None => Ok(Vec::new()),
+  }
+}
+
+fn get_connection(cell_name: &str, cell: &YosysCell, port: &str) -> Result<YosysBit, String> {
+  match cell.connections.get(port).map(|bits| &bits[..]) {
+    Some([bit]) => Ok(*bit),
+    Some(bits) => Err(format!("Cell {}: expected port {} to be one bit wide, got {}", cell_name, port, bits.len())),
+    None => Err(format!("Cell {}: missing connection for port {}", cell_name, port)),
+  }
+}
+
+fn set_driver(drivers: &mut HashMap<usize, OutputSpot>, bit: YosysBit, spot: OutputSpot, what: &str) -> Result<(), String> {
+  match bit {
+    YosysBit::Net(net) => match drivers.insert(net, spot) {
+      None => Ok(()),
+      Some(_) => Err(format!("Net {} has multiple drivers (second driver: {})", net, what)),
+    },
+    YosysBit::Const(_) => Err(format!("{} drives a constant", what)),
+  }
+}
+
+struct FlipFlopFeatures {
+  clock_edge: ClockEdge,
+  has_enable: bool,
+  /// The set/reset behaviour, along with the port that triggers it.
+  reset: Option<(ResetMode, ResetValue, &'static str)>,
+}
+
+/// Describes the flip-flop cells we know how to pack into a logic cell.
+fn flip_flop_features(cell_type: &str) -> Option<FlipFlopFeatures> {
+  let rest = cell_type.strip_prefix("SB_DFF")?;
+  let (clock_edge, rest) = match rest.strip_prefix('N') {
+    Some(rest) => (ClockEdge::Falling, rest),
+    None => (ClockEdge::Rising, rest),
+  };
+  let (has_enable, rest) = match rest.strip_prefix('E') {
+    Some(rest) => (true, rest),
+    None => (false, rest),
+  };
+  let reset = match rest {
+    "" => None,
+    "R" => Some((ResetMode::Async, ResetValue::Clear, "R")),
+    "S" => Some((ResetMode::Async, ResetValue::Set, "S")),
+    "SR" => Some((ResetMode::Sync, ResetValue::Clear, "R")),
+    "SS" => Some((ResetMode::Sync, ResetValue::Set, "S")),
+    _ => return None,
+  };
+  Some(FlipFlopFeatures { clock_edge, has_enable, reset })
+}
+
+/// The read and write clock edges of the block RAM cells. The NR and NW variants have inverted read and write clocks.
+fn ram_clock_edges(cell_type: &str) -> Option<(ClockEdge, ClockEdge)> {
+  match cell_type {
+    "SB_RAM40_4K" => Some((ClockEdge::Rising, ClockEdge::Rising)),
+    "SB_RAM40_4KNR" => Some((ClockEdge::Falling, ClockEdge::Rising)),
+    "SB_RAM40_4KNW" => Some((ClockEdge::Rising, ClockEdge::Falling)),
+    "SB_RAM40_4KNRNW" => Some((ClockEdge::Falling, ClockEdge::Falling)),
+    _ => None,
+  }
+}
+
+/// Whether a port of SB_MAC16, as Yosys names it (with buses like O[31:0] in one piece), is an output.
+fn is_mac16_output_bus(port: &str) -> bool {
+  matches!(port, "O" | "CO" | "ACCUMCO" | "SIGNEXTOUT")
+}
+
+/// Whether a port (or bus) of a cell is one of its outputs, which drive nets rather than use them.
+fn is_cell_output(cell_type: &str, port: &str) -> bool {
+  let port_name = || PortName::try_from(port).ok();
+  match cell_type {
+    "SB_LUT4" => port == "O",
+    "SB_CARRY" => port == "CO",
+    "SB_IO" => matches!(port, "D_IN_0" | "D_IN_1"),
+    "SB_MAC16" => is_mac16_output_bus(port),
+    "SB_SPRAM256KA" => port == "DATAOUT",
+    "SB_HFOSC" => port == "CLKHF",
+    "SB_LFOSC" => port == "CLKLF",
+    "SB_PLL40_CORE" | "SB_PLL40_PAD" => matches!(port, "PLLOUTCORE" | "PLLOUTGLOBAL") || port_name().is_some_and(is_pll_output),
+    "SB_RGBA_DRV" => matches!(port, "RGB0" | "RGB1" | "RGB2"),
+    "SB_LEDDA_IP" => port_name().is_some_and(is_fixed_cell_output),
+    "SB_I2C" | "SB_SPI" | "SB_WARMBOOT" => port_name().is_some_and(is_hard_ip_output),
+    cell_type if ram_clock_edges(cell_type).is_some() => port == "RDATA",
+    cell_type if flip_flop_features(cell_type).is_some() => port == "Q",
+    _ => false,
+  }
+}
+
+/// A LUT4 table that just passes input 0 through, for flip-flops that can't be packed with a LUT.
+const PASS_THROUGH_TABLE: u16 = 0xaaaa;
+
+/// A LUT4 table that passes input 3 through, for getting a carry out onto the general routing.
+const CARRY_FEED_OUT_TABLE: u16 = 0xff00;
+
+struct Carry<'a> {
+  cell_name: &'a str,
+  carry_in: YosysBit,
+  inputs: [YosysBit; 2],
+  carry_out: YosysBit,
+}
+
+/// Converts the JSON netlist written by Yosys' `synth_ice40 -json` into a PnrProblem.
+/// Every bit of every top-level port must be bound to an IO pin in `port_spots`.
+pub fn parse(
+  content: &str,
+  port_spots: &HashMap<String, IoPinSpot>,
+) -> Result<PnrProblem, String> {
+  let json: YosysJson = serde_json::from_str(content).map_err(|e| format!("Invalid Yosys JSON: {}", e))?;
+  let (top_name, top) = get_top_module(&json)?;
+  println!("Reading top module {}", top_name);
+
+  let mut problem = PnrProblem::new();
+  let mut drivers: HashMap<usize, OutputSpot> = HashMap::new();
+  let mut sinks: Vec<(YosysBit, InputSpot)> = Vec::new();
+
+  // Ports wired to the PACKAGE_PIN of an SB_IO cell (or the PACKAGEPIN of an SB_PLL40_PAD) are configured by that cell.
+  let mut sb_io_pins = HashMap::new();
+  for (cell_name, cell) in &top.cells {
+    match cell.cell_type.as_str() {
+      "SB_IO" => sb_io_pins.insert(get_connection(cell_name, cell, "PACKAGE_PIN")?, None),
+      "SB_PLL40_PAD" => sb_io_pins.insert(get_connection(cell_name, cell, "PACKAGEPIN")?, None),
+      _ => None,
+    };
+  }
+  // The RGB LED pins are driven by the SB_RGBA_DRV cell alone, and aren't configured as IOs.
+  let mut rgb_pins = HashSet::new();
+  for cell in top.cells.values().filter(|cell| cell.cell_type == "SB_RGBA_DRV") {
+    for port in ["RGB0", "RGB1", "RGB2"] {
+      rgb_pins.extend(cell.connections.get(port).into_iter().flatten().copied());
+    }
+  }
+
+  // Bind ports to pins.
+  for (port_name, port) in &top.ports {
+    for (i, &bit) in port.bits.iter().enumerate() {
+      let name = port_bit_name(port_name, port.bits.len(), i);
+      if rgb_pins.contains(&bit) {
+        continue;
+      }
+      let &spot = port_spots.get(&name).ok_or_else(|| format!("Port {} is not bound to a pin", name))?;
+      if let Some(pin) = sb_io_pins.get_mut(&bit) {
+        *pin = Some((name, spot));
+        continue;
+      }
+      match port.direction.as_str() {
+        "input" => {
+          problem.used_ios.push(UsedIo { name: Some(name.clone()), spot, direction: IoDirection::Input, config: IoConfig::default() });
+          set_driver(&mut drivers, bit, OutputSpot::Pin(spot), &format!("port {}", name))?;
+        }
+        "output" => {
+          problem.used_ios.push(UsedIo { name: Some(name.clone()), spot, direction: IoDirection::Output, config: IoConfig::default() });
+          sinks.push((bit, InputSpot::Pin(spot)));
+        }
+        direction => return Err(format!("Port {}: unsupported direction {}", name, direction)),
+      }
+    }
+  }
+
+  // Count how many places each net is used, so we know which flip-flops can be packed into their LUT.
+  let mut use_counts: HashMap<YosysBit, usize> = HashMap::new();
+  for port in top.ports.values().filter(|port| port.direction == "output") {
+    for &bit in &port.bits {
+      *use_counts.entry(bit).or_default() += 1;
+    }
+  }
+  for cell in top.cells.values() {
+    for (port, bits) in &cell.connections {
+      if !is_cell_output(&cell.cell_type, port) {
+        for &bit in bits {
+          *use_counts.entry(bit).or_default() += 1;
+        }
+      }
+    }
+  }
+
+  // Create all of the LUTs.
+  let mut lut_inputs = Vec::new();
+  let mut lut_by_output = HashMap::new();
+  let mut unsupported: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+  for (cell_name, cell) in &top.cells {
+    match cell.cell_type.as_str() {
+      "SB_LUT4" => {
+        let lut_index = LutIndex(problem.lut4s.len());
+        let table = get_param(cell_name, cell, "LUT_INIT")?;
+        let table = u16::try_from(table).map_err(|_| format!("Cell {}: LUT_INIT out of range: {}", cell_name, table))?;
+        problem.lut4s.push(Lut4::combinational(Some(cell_name.clone()), table));
+        let mut inputs = Vec::new();
+        for port in ["I0", "I1", "I2", "I3"] {
+          inputs.push(get_connection(cell_name, cell, port)?);
+        }
+        lut_inputs.push(inputs);
+        let output = get_connection(cell_name, cell, "O")?;
+        set_driver(&mut drivers, output, OutputSpot::Lut { lut_index }, &format!("cell {}", cell_name))?;
+        lut_by_output.insert(output, lut_index);
+      }
+      "SB_IO" => {
+        let package_pin = get_connection(cell_name, cell, "PACKAGE_PIN")?;
+        let Some((name, spot)) = sb_io_pins[&package_pin].clone() else {
+          return Err(format!("Cell {}: PACKAGE_PIN must be connected directly to a top-level port", cell_name));
+        };
+        let pin_type = get_param(cell_name, cell, "PIN_TYPE")?;
+        let pin_type = u8::try_from(pin_type).ok().filter(|&pin_type| pin_type < 64)
+          .ok_or_else(|| format!("Cell {}: PIN_TYPE out of range: {}", cell_name, pin_type))?;
+        let pull_up = cell.parameters.contains_key("PULLUP") && get_param(cell_name, cell, "PULLUP")? != 0;
+        let (config, has_output) = IoConfig::from_pin_type(pin_type, pull_up).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+        let connection = |port| cell.connections.get(port).and_then(|bits| bits.first().copied());
+        let what = format!("cell {}", cell_name);
+        let mut has_input = false;
+        for (port, driver) in [("D_IN_0", OutputSpot::Pin(spot)), ("D_IN_1", OutputSpot::PinDdrIn(spot))] {
+          if let Some(bit @ YosysBit::Net(_)) = connection(port) {
+            set_driver(&mut drivers, bit, driver, &what)?;
+            has_input = true;
+          }
+        }
+        // An output pin whose input is also read, like an I2C bus line, is bidirectional.
+        let direction = match (has_output, has_input) {
+          (false, _) => IoDirection::Input,
+          (true, false) => IoDirection::Output,
+          (true, true) => IoDirection::Bidirectional,
+        };
+        problem.used_ios.push(UsedIo { name: Some(name), spot, direction, config });
+        let mut cell_sinks = Vec::new();
+        if has_output {
+          cell_sinks.push(("D_OUT_0", InputSpot::Pin(spot)));
+          if config.output_mode == IoMode::Ddr {
+            cell_sinks.push(("D_OUT_1", InputSpot::PinDdrOut(spot)));
+          }
+          if config.output_enable != OutputEnable::Always {
+            cell_sinks.push(("OUTPUT_ENABLE", InputSpot::PinOutputEnable(spot)));
+          }
+        }
+        let io = problem.used_ios.last().unwrap();
+        if io.uses_input_register() {
+          cell_sinks.push(("INPUT_CLK", InputSpot::IoInputClock { tile: spot.tile }));
+        }
+        if io.uses_output_register() {
+          cell_sinks.push(("OUTPUT_CLK", InputSpot::IoOutputClock { tile: spot.tile }));
+        }
+        for (port, to) in cell_sinks {
+          if let Some(bit) = connection(port) {
+            sinks.push((bit, to));
+          }
+        }
+        // The clock enable is high when left unconnected.
+        if let Some(bit @ YosysBit::Net(_)) = connection("CLOCK_ENABLE") {
+          sinks.push((bit, InputSpot::IoClockEnable { tile: spot.tile }));
+        }
+      }
+      "SB_CARRY" => {}
+      "SB_MAC16" => {
+        let dsp_index = DspIndex(problem.dsps.len());
+        let mut dsp = Dsp { name: Some(cell_name.clone()), parameters: BTreeMap::new() };
+        for (parameter, _) in MAC16_PARAMETERS {
+          dsp.parameters.insert(parameter.to_string(), get_param_or_default(cell_name, cell, parameter)?);
+        }
+        if get_param_or_default(cell_name, cell, "NEG_TRIGGER")? != 0 {
+          return Err(format!("Cell {}: NEG_TRIGGER isn't supported", cell_name));
+        }
+        problem.dsps.push(dsp);
+        for (port, bits) in &cell.connections {
+          for (i, &bit) in bits.iter().enumerate() {
+            let port_name = if bits.len() == 1 { port.clone() } else { format!("{}_{}", port, i) };
+            let port_name = PortName::try_from(port_name).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+            match is_mac16_output_bus(port) {
+              true => if let YosysBit::Net(_) = bit {
+                set_driver(&mut drivers, bit, OutputSpot::Dsp { dsp_index, port: port_name }, &format!("cell {}", cell_name))?;
+              },
+              false => sinks.push((bit, InputSpot::Dsp { dsp_index, port: port_name })),
+            }
+          }
+        }
+        // The clock enable is high when left unconnected.
+        if !cell.connections.contains_key("CE") {
+          sinks.push((YosysBit::Const(true), InputSpot::Dsp { dsp_index, port: PortName::try_from("CE")? }));
+        }
+      }
+      "SB_HFOSC" | "SB_LFOSC" => {
+        let (driver, output_port, control): (_, _, fn(PortName) -> InputSpot) = match cell.cell_type.as_str() {
+          "SB_HFOSC" => {
+            let divider = get_param_or_default(cell_name, cell, "CLKHF_DIV")?;
+            let divider = u8::try_from(divider).ok().filter(|&divider| divider < 4)
+              .ok_or_else(|| format!("Cell {}: CLKHF_DIV out of range: {}", cell_name, divider))?;
+            (OutputSpot::HfOsc { divider }, "CLKHF", |port| InputSpot::HfOsc { port })
+          }
+          _ => (OutputSpot::LfOsc, "CLKLF", |port| InputSpot::LfOsc { port }),
+        };
+        for port in cell.connections.keys() {
+          let bit = get_connection(cell_name, cell, port)?;
+          match port == output_port {
+            true => if let YosysBit::Net(_) = bit {
+              set_driver(&mut drivers, bit, driver, &format!("cell {}", cell_name))?;
+            },
+            false => sinks.push((bit, control(PortName::try_from(port.as_str()).map_err(|e| format!("Cell {}: {}", cell_name, e))?))),
+          }
+        }
+      }
+      "SB_PLL40_CORE" | "SB_PLL40_PAD" => {
+        let pll_index = PllIndex(problem.plls.len());
+        for (param, supported) in [("FEEDBACK_PATH", "SIMPLE"), ("PLLOUT_SELECT", "GENCLK")] {
+          if let Some(value) = get_string_param(cell, param) && value != supported {
+            return Err(format!("Cell {}: only {} {} is supported, got {}", cell_name, param, supported, value));
+          }
+        }
+        let divider = |param| {
+          let value = get_param_or_default(cell_name, cell, param)?;
+          u8::try_from(value).map_err(|_| format!("Cell {}: {} out of range: {}", cell_name, param, value))
+        };
+        let dividers = PllDividers {
+          divr: divider("DIVR")?,
+          divf: divider("DIVF")?,
+          divq: divider("DIVQ")?,
+          filter_range: divider("FILTER_RANGE")?,
+        };
+        let reference = match cell.cell_type.as_str() {
+          "SB_PLL40_PAD" => {
+            let package_pin = get_connection(cell_name, cell, "PACKAGEPIN")?;
+            let Some((name, spot)) = sb_io_pins[&package_pin].clone() else {
+              return Err(format!("Cell {}: PACKAGEPIN must be connected directly to a top-level port", cell_name));
+            };
+            problem.used_ios.push(UsedIo { name: Some(name), spot, direction: IoDirection::Input, config: IoConfig::default() });
+            PllReference::Pad
+          }
+          _ => PllReference::Core,
+        };
+        problem.plls.push(Pll { name: Some(cell_name.clone()), reference, dividers });
+        for (port, bits) in &cell.connections {
+          for (i, &bit) in bits.iter().enumerate() {
+            // Both the core and the global output are PLLOUT_A, which goes onto a global network if it clocks anything.
+            let port_name = match port.as_str() {
+              "PACKAGEPIN" => continue,
+              "PLLOUTCORE" | "PLLOUTGLOBAL" => "PLLOUT_A".to_string(),
+              _ if bits.len() == 1 => port.clone(),
+              _ => format!("{}_{}", port, i),
+            };
+            let port_name = PortName::try_from(port_name).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+            match is_pll_output(port_name) {
+              true => if let YosysBit::Net(_) = bit {
+                set_driver(&mut drivers, bit, OutputSpot::Pll { pll_index, port: port_name }, &format!("cell {}", cell_name))?;
+              },
+              false => sinks.push((bit, InputSpot::Pll { pll_index, port: port_name })),
+            }
+          }
+        }
+      }
+      "SB_RGBA_DRV" => {
+        if problem.rgb_driver.is_some() {
+          return Err(format!("Cell {}: there's only one RGB driver", cell_name));
+        }
+        let current_mode = match get_param_or_default(cell_name, cell, "CURRENT_MODE")? {
+          0 => RgbCurrentMode::Full,
+          _ => RgbCurrentMode::Half,
+        };
+        let current = |param| {
+          let value = get_param_or_default(cell_name, cell, param)?;
+          u8::try_from(value).map_err(|_| format!("Cell {}: {} out of range: {}", cell_name, param, value))
+        };
+        problem.rgb_driver = Some(RgbDriver {
+          name: Some(cell_name.clone()),
+          current_mode,
+          rgb0_current: current("RGB0_CURRENT")?,
+          rgb1_current: current("RGB1_CURRENT")?,
+          rgb2_current: current("RGB2_CURRENT")?,
+        });
+        for port in cell.connections.keys().filter(|port| !matches!(port.as_str(), "RGB0" | "RGB1" | "RGB2")) {
+          let bit = get_connection(cell_name, cell, port)?;
+          let port = PortName::try_from(port.as_str()).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+          sinks.push((bit, InputSpot::RgbDriver { port }));
+        }
+      }
+      "SB_LEDDA_IP" => {
+        for port in cell.connections.keys() {
+          let bit = get_connection(cell_name, cell, port)?;
+          let port = PortName::try_from(port.as_str()).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+          match is_fixed_cell_output(port) {
+            true => if let YosysBit::Net(_) = bit {
+              set_driver(&mut drivers, bit, OutputSpot::LedDriver { port }, &format!("cell {}", cell_name))?;
+            },
+            false => sinks.push((bit, InputSpot::LedDriver { port })),
+          }
+        }
+      }
+      "SB_I2C" | "SB_SPI" | "SB_WARMBOOT" => {
+        let hard_ip_index = HardIpIndex(problem.hard_ips.len());
+        let cell_type = cell.cell_type.trim_start_matches("SB_").to_string();
+        // The upper address bits of the system bus pick the block: 0b0001 and 0b0011 for the left and right I2C blocks,
+        // 0b0000 and 0b0010 for the SPI blocks.
+        let site = match cell.parameters.contains_key("BUS_ADDR74") {
+          true => Some((get_param(cell_name, cell, "BUS_ADDR74")? as usize >> 1) & 1),
+          false => None,
+        };
+        let mut config = Vec::new();
+        for param in ["SDA_INPUT_DELAYED", "SDA_OUTPUT_DELAYED"] {
+          if get_param_or_default(cell_name, cell, param)? != 0 {
+            config.push(param.to_string());
+          }
+        }
+        problem.hard_ips.push(HardIp { name: Some(cell_name.clone()), cell_type, site, config });
+        for (port, bits) in &cell.connections {
+          for (i, &bit) in bits.iter().enumerate() {
+            let port_name = if bits.len() == 1 { port.clone() } else { format!("{}_{}", port, i) };
+            let port_name = PortName::try_from(port_name).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+            match is_hard_ip_output(port_name) {
+              true => if let YosysBit::Net(_) = bit {
+                set_driver(&mut drivers, bit, OutputSpot::HardIp { hard_ip_index, port: port_name }, &format!("cell {}", cell_name))?;
+              },
+              false => sinks.push((bit, InputSpot::HardIp { hard_ip_index, port: port_name })),
+            }
+          }
+        }
+      }
+      "SB_SPRAM256KA" => {
+        let spram_index = SpramIndex(problem.sprams.len());
+        problem.sprams.push(Spram { name: Some(cell_name.clone()) });
+        for (port, bits) in &cell.connections {
+          for (i, &bit) in bits.iter().enumerate() {
+            let port_name = if bits.len() == 1 { port.clone() } else { format!("{}_{}", port, i) };
+            let port_name = PortName::try_from(port_name).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+            match port.as_str() == "DATAOUT" {
+              true => if let YosysBit::Net(_) = bit {
+                set_driver(&mut drivers, bit, OutputSpot::Spram { spram_index, port: port_name }, &format!("cell {}", cell_name))?;
+              },
+              false => sinks.push((bit, InputSpot::Spram { spram_index, port: port_name })),
+            }
+          }
+        }
+        // POWEROFF is active low, so an SPRAM is powered on when it's left unconnected.
+        if !cell.connections.contains_key("POWEROFF") {
+          sinks.push((YosysBit::Const(true), InputSpot::Spram { spram_index, port: PortName::try_from("POWEROFF")? }));
+        }
+      }
+      cell_type if let Some((read_clock_edge, write_clock_edge)) = ram_clock_edges(cell_type) => {
+        let ram_index = RamIndex(problem.rams.len());
+        let mut ram = Ram::new(Some(cell_name.clone()));
+        ram.read_clock_edge = read_clock_edge;
+        ram.write_clock_edge = write_clock_edge;
+        ram.read_mode = RamMode::from_mode_bits(get_param_or_default(cell_name, cell, "READ_MODE")?).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+        ram.write_mode = RamMode::from_mode_bits(get_param_or_default(cell_name, cell, "WRITE_MODE")?).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+        for i in 0..16 {
+          let bits = get_wide_param(cell_name, cell, &format!("INIT_{:X}", i))?;
+          for word in 0..16 {
+            let value = (0..16).filter(|bit| bits.get(16 * word + bit) == Some(&true)).map(|bit| 1 << bit).sum();
+            ram.init.push(value);
+          }
+        }
+        problem.rams.push(ram);
+        for (port, bits) in &cell.connections {
+          for (i, &bit) in bits.iter().enumerate() {
+            // The NR and NW variants call their clocks RCLKN and WCLKN.
+            let port = port.strip_suffix("CLKN").map_or(port.clone(), |prefix| format!("{}CLK", prefix));
+            let pin_name = if bits.len() == 1 { port } else { format!("{}_{}", port, i) };
+            let pin = RamPin::try_from(pin_name).map_err(|e| format!("Cell {}: {}", cell_name, e))?;
+            match pin.is_output() {
+              true => if let YosysBit::Net(_) = bit {
+                set_driver(&mut drivers, bit, OutputSpot::Ram { ram_index, pin }, &format!("cell {}", cell_name))?;
+              },
+              false => sinks.push((bit, InputSpot::Ram { ram_index, pin })),
+            }
+          }
+        }
+        // The clock enables are high when left unconnected.
+        for (port, pin) in [("RCLKE", RamPin::ReadClockEnable), ("WCLKE", RamPin::WriteClockEnable)] {
+          if !cell.connections.contains_key(port) {
+            sinks.push((YosysBit::Const(true), InputSpot::Ram { ram_index, pin }));
+          }
+        }
+      }
+      cell_type if flip_flop_features(cell_type).is_some() => {}
+      cell_type => unsupported.entry(cell_type).or_default().push(cell_name),
+    }
+  }
+  if !unsupported.is_empty() {
+    let mut message = "Netlist contains unsupported cells:".to_string();
+    for (cell_type, cell_names) in &unsupported {
+      message += &format!("\n  {} ({} cells): {}", cell_type, cell_names.len(), cell_names.join(", "));
+    }
+    return Err(message);
+  }
+
+  // Pack each carry into a logic cell, preferably one whose LUT already has the carry's inputs on inputs 1 and 2.
+  let mut carries = Vec::new();
+  for (cell_name, cell) in &top.cells {
+    if cell.cell_type == "SB_CARRY" {
+      carries.push(Carry {
+        cell_name,
+        carry_in: get_connection(cell_name, cell, "CI")?,
+        inputs: [get_connection(cell_name, cell, "I0")?, get_connection(cell_name, cell, "I1")?],
+        carry_out: get_connection(cell_name, cell, "CO")?,
+      });
+    }
+  }
+  let mut carry_luts = Vec::new();
+  let mut paired_luts = HashSet::new();
+  for carry in &carries {
+    let paired = (0..lut_inputs.len()).map(LutIndex).find(|lut_index| {
+      !paired_luts.contains(lut_index) && lut_inputs[lut_index.0][1..3] == carry.inputs
+    });
+    let lut_index = paired.unwrap_or_else(|| {
+      problem.lut4s.push(Lut4::combinational(Some(carry.cell_name.to_string()), 0));
+      lut_inputs.push(vec![YosysBit::Const(false), carry.inputs[0], carry.inputs[1]]);
+      LutIndex(problem.lut4s.len() - 1)
+    });
+    paired_luts.insert(lut_index);
+    carry_luts.push(lut_index);
+  }
+
+  // Follow the carry outs to string the carries together into chains.
+  let mut next_carry = HashMap::new();
+  for (i, carry) in carries.iter().enumerate() {
+    if let YosysBit::Net(_) = carry.carry_in
+      && let Some(prev) = carries.iter().position(|c| c.carry_out == carry.carry_in)
+      && next_carry.insert(prev, i).is_some()
+    {
+      return Err(format!("Cell {}: carry out drives more than one SB_CARRY", carries[prev].cell_name));
+    }
+  }
+  let mut chained_count = 0;
+  for (head, carry) in carries.iter().enumerate() {
+    if next_carry.values().any(|&i| i == head) {
+      continue;
+    }
+    let mut chain = CarryChain { luts: Vec::new(), carry_in: false };
+    match carry.carry_in {
+      YosysBit::Const(value) => chain.carry_in = value,
+      // The carry in comes from the general routing, so feed it in through an extra logic cell.
+      carry_in => {
+        problem.lut4s.push(Lut4::combinational(Some(format!("{}$feed_in", carry.cell_name)), 0));
+        lut_inputs.push(vec![YosysBit::Const(false), carry_in, YosysBit::Const(true)]);
+        chain.luts.push(LutIndex(problem.lut4s.len() - 1));
+      }
+    }
+    let mut i = head;
+    loop {
+      let carry = &carries[i];
+      chain.luts.push(carry_luts[i]);
+      chained_count += 1;
+      let mut other_uses = use_counts.get(&carry.carry_out).copied().unwrap_or(0);
+      let next = next_carry.get(&i).copied();
+      if let Some(next) = next {
+        other_uses -= 1;
+        if lut_inputs[carry_luts[next].0].get(3) == Some(&carry.carry_out) {
+          other_uses -= 1;
+        }
+      }
+      match (next, other_uses) {
+        (_, 0) => set_driver(&mut drivers, carry.carry_out, OutputSpot::CarryOut { lut_index: carry_luts[i] }, carry.cell_name)?,
+        (Some(_), _) => return Err(format!("Cell {}: carry out is used outside of its carry chain, which isn't supported", carry.cell_name)),
+        // The carry out leaves the end of the chain, so feed it out through an extra logic cell.
+        (None, _) => {
+          let lut_index = LutIndex(problem.lut4s.len());
+          problem.lut4s.push(Lut4::combinational(Some(format!("{}$feed_out", carry.cell_name)), CARRY_FEED_OUT_TABLE));
+          lut_inputs.push(Vec::new());
+          let to = InputSpot::Lut { lut_index, input_index: 3 };
+          problem.wires.push(Wire { from: OutputSpot::CarryOut { lut_index: carry_luts[i] }, to });
+          set_driver(&mut drivers, carry.carry_out, OutputSpot::Lut { lut_index }, carry.cell_name)?;
+          chain.luts.push(lut_index);
+        }
+      }
+      match next {
+        Some(next) => i = next,
+        None => break,
+      }
+    }
+    problem.carry_chains.push(chain);
+  }
+  if chained_count != carries.len() {
+    return Err("Netlist contains a loop of SB_CARRY cells".to_string());
+  }
+
+  // Create all of the flip-flops, packing each into the LUT that drives it when nothing else uses the LUT's output.
+  let mut packed_luts = HashSet::new();
+  for (cell_name, cell) in &top.cells {
+    let Some(FlipFlopFeatures { clock_edge, has_enable, reset }) = flip_flop_features(&cell.cell_type) else {
+      continue;
+    };
+    let clock = get_connection(cell_name, cell, "C")?;
+    let data = get_connection(cell_name, cell, "D")?;
+    let q = get_connection(cell_name, cell, "Q")?;
+    let lut_index = match (data, lut_by_output.get(&data)) {
+      (YosysBit::Net(net), Some(&lut_index)) if use_counts[&data] == 1 && !packed_luts.contains(&lut_index) => {
+        drivers.remove(&net);
+        lut_index
+      }
+      _ => {
+        let lut_index = LutIndex(problem.lut4s.len());
+        problem.lut4s.push(Lut4::combinational(Some(cell_name.clone()), PASS_THROUGH_TABLE));
+        lut_inputs.push(vec![data]);
+        lut_index
+      }
+    };
+    problem.lut4s[lut_index.0].clock_edge = clock_edge;
+    if has_enable {
+      sinks.push((get_connection(cell_name, cell, "E")?, InputSpot::ClockEnable { lut_index }));
+    }
+    if let Some((reset_mode, reset_value, port)) = reset {
+      let lut = &mut problem.lut4s[lut_index.0];
+      lut.reset_mode = reset_mode;
+      lut.reset_value = reset_value;
+      sinks.push((get_connection(cell_name, cell, port)?, InputSpot::SetReset { lut_index }));
+    }
+    packed_luts.insert(lut_index);
+    // PnrProblem::promote_globals puts the clock on a global network.
+    sinks.push((clock, InputSpot::Clock { lut_index }));
+    set_driver(&mut drivers, q, OutputSpot::Lut { lut_index }, &format!("cell {}", cell_name))?;
+  }
+
+  // Hook up LUT inputs.
+  for (i, inputs) in lut_inputs.into_iter().enumerate() {
+    for (input_index, bit) in inputs.into_iter().enumerate() {
+      sinks.push((bit, InputSpot::Lut { lut_index: LutIndex(i), input_index: input_index as u8 }));
+    }
+  }
+
+  // Produce the wires. Constants are left for PnrProblem::fold_constants to deal with.
+  for (bit, to) in sinks {
+    let from = match bit {
+      YosysBit::Net(net) => *drivers.get(&net).ok_or_else(|| format!("Net {} has no driver", net))?,
+      YosysBit::Const(value) => OutputSpot::Const { value },
+    };
+    problem.wires.push(Wire { from, to });
+  }
+
+  println!(
+    "Read {} IOs, {} LUTs and {} wires from netlist",
+    problem.used_ios.len(), problem.lut4s.len(), problem.wires.len(),
+  );
+  Ok(problem)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chipdb::TilePos;
+
+  #[test]
+  fn cell_outputs() {
+    assert!(is_cell_output("SB_LUT4", "O"));
+    assert!(!is_cell_output("SB_LUT4", "I0"));
+    assert!(is_cell_output("SB_CARRY", "CO"));
+    assert!(!is_cell_output("SB_CARRY", "CI"));
+    assert!(is_cell_output("SB_IO", "D_IN_1"));
+    assert!(!is_cell_output("SB_IO", "D_OUT_0"));
+    assert!(is_cell_output("SB_MAC16", "O"));
+    assert!(is_cell_output("SB_MAC16", "ACCUMCO"));
+    assert!(!is_cell_output("SB_MAC16", "A"));
+    assert!(is_cell_output("SB_SPRAM256KA", "DATAOUT"));
+    assert!(!is_cell_output("SB_SPRAM256KA", "DATAIN"));
+    assert!(is_cell_output("SB_HFOSC", "CLKHF"));
+    assert!(!is_cell_output("SB_HFOSC", "CLKHFEN"));
+    assert!(is_cell_output("SB_LFOSC", "CLKLF"));
+    assert!(is_cell_output("SB_PLL40_CORE", "PLLOUTGLOBAL"));
+    assert!(is_cell_output("SB_PLL40_PAD", "LOCK"));
+    assert!(!is_cell_output("SB_PLL40_CORE", "REFERENCECLK"));
+    assert!(is_cell_output("SB_RGBA_DRV", "RGB1"));
+    assert!(!is_cell_output("SB_RGBA_DRV", "RGB1PWM"));
+    assert!(is_cell_output("SB_LEDDA_IP", "PWMOUT2"));
+    assert!(!is_cell_output("SB_LEDDA_IP", "LEDDCLK"));
+    assert!(is_cell_output("SB_SPI", "SBDATO"));
+    assert!(is_cell_output("SB_I2C", "SDAOE"));
+    assert!(!is_cell_output("SB_I2C", "SBADRI"));
+    assert!(is_cell_output("SB_DFF", "Q"));
+    assert!(!is_cell_output("SB_DFF", "D"));
+    assert!(is_cell_output("SB_DFFESR", "Q"));
+    assert!(!is_cell_output("SB_DFFESR", "E"));
+  }
+
+  /// A LUT whose output only feeds a flip-flop is packed with it, but not one whose output is also used elsewhere.
+  #[test]
+  fn flip_flops_pack_into_their_lut() {
+    let netlist = |also_out: bool| format!(r#"{{"modules": {{"top": {{
+      "ports": {{"a": {{"direction": "input", "bits": [2]}}, "clk": {{"direction": "input", "bits": [3]}},
+                 "q": {{"direction": "output", "bits": [5]}}, "d": {{"direction": "output", "bits": [{}]}}}},
+      "cells": {{
+        "lut": {{"type": "SB_LUT4", "parameters": {{"LUT_INIT": "0101010101010101"}},
+                 "connections": {{"I0": [2], "I1": ["0"], "I2": ["0"], "I3": ["0"], "O": [4]}}}},
+        "ff": {{"type": "SB_DFF", "connections": {{"C": [3], "D": [4], "Q": [5]}}}}
+      }}}}}}}}"#, if also_out { 4 } else { 2 });
+    let port_spots: HashMap<String, IoPinSpot> = ["a", "clk", "q", "d"].iter().enumerate()
+      .map(|(i, name)| (name.to_string(), IoPinSpot { tile: TilePos(i as u8 + 1, 0), which: 0 }))
+      .collect();
+    assert_eq!(parse(&netlist(false), &port_spots).unwrap().lut4s.len(), 1);
+    assert_eq!(parse(&netlist(true), &port_spots).unwrap().lut4s.len(), 2);
+  }
+
+  #[test]
+  fn flip_flop_cell_types() {
+    let features = |cell_type| flip_flop_features(cell_type).map(|features| (features.has_enable, features.reset));
+    assert_eq!(features("SB_DFF"), Some((false, None)));
+    assert_eq!(features("SB_DFFE"), Some((true, None)));
+    assert_eq!(features("SB_DFFR"), Some((false, Some((ResetMode::Async, ResetValue::Clear, "R")))));
+    assert_eq!(features("SB_DFFESS"), Some((true, Some((ResetMode::Sync, ResetValue::Set, "S")))));
+    let clock_edge = |cell_type| flip_flop_features(cell_type).map(|features| features.clock_edge);
+    assert_eq!(clock_edge("SB_DFF"), Some(ClockEdge::Rising));
+    assert_eq!(clock_edge("SB_DFFNESR"), Some(ClockEdge::Falling));
+    assert_eq!(features("SB_DFFNESR"), Some((true, Some((ResetMode::Sync, ResetValue::Clear, "R")))));
+    assert!(features("SB_DFFX").is_none());
+    assert!(features("SB_LUT4").is_none());
+  }
+
+  #[test]
+  fn clocks_are_left_for_promote_globals() {
+    let netlist = r#"{"modules": {"top": {
+      "ports": {"clk": {"direction": "input", "bits": [2]}, "q": {"direction": "output", "bits": [3]}},
+      "cells": {"ff": {"type": "SB_DFF", "connections": {"C": [2], "D": [3], "Q": [3]}}}
+    }}}"#;
+    let clk = IoPinSpot { tile: TilePos(6, 0), which: 1 };
+    let port_spots = HashMap::from([("clk".to_string(), clk), ("q".to_string(), IoPinSpot { tile: TilePos(7, 0), which: 0 })]);
+    let problem = parse(netlist, &port_spots).unwrap();
+    assert_eq!(problem.lut4s[0].clock_domain, None);
+    assert!(problem.wires.iter().any(|wire| wire.from == OutputSpot::Pin(clk) && matches!(wire.to, InputSpot::Clock { lut_index: LutIndex(0) })));
+  }
+
+  #[test]
+  fn oscillator_dividers_are_read_from_clkhf_div() {
+    let netlist = r#"{"modules": {"top": {
+      "ports": {"q": {"direction": "output", "bits": [3]}},
+      "cells": {
+        "osc": {"type": "SB_HFOSC", "parameters": {"CLKHF_DIV": "0b10"}, "connections": {"CLKHFEN": ["1"], "CLKHF": [2]}},
+        "ff": {"type": "SB_DFF", "connections": {"C": [2], "D": [3], "Q": [3]}}
+      }
+    }}}"#;
+    let port_spots = HashMap::from([("q".to_string(), IoPinSpot { tile: TilePos(7, 0), which: 0 })]);
+    let problem = parse(netlist, &port_spots).unwrap();
+    assert!(problem.wires.iter().any(|wire| wire.from == OutputSpot::HfOsc { divider: 2 } && matches!(wire.to, InputSpot::Clock { .. })));
+    let netlist = netlist.replace("0b10", "0b100");
+    assert!(parse(&netlist, &port_spots).unwrap_err().contains("CLKHF_DIV out of range: 4"));
+  }
+}